@@ -0,0 +1,103 @@
+use cubism::core::{Drawable, DynamicFlags};
+
+/// What changed about a drawable since it was last compared.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DrawableChanges {
+    pub visibility: bool,
+    pub opacity: bool,
+    pub draw_order: bool,
+    pub render_order: bool,
+    pub vertex_positions: bool,
+}
+
+impl DrawableChanges {
+    const ALL: Self = Self {
+        visibility: true,
+        opacity: true,
+        draw_order: true,
+        render_order: true,
+        vertex_positions: true,
+    };
+
+    pub fn any(&self) -> bool {
+        self.visibility
+            || self.opacity
+            || self.draw_order
+            || self.render_order
+            || self.vertex_positions
+    }
+}
+
+struct DrawableState {
+    visible: bool,
+    opacity: f32,
+    draw_order: i32,
+    render_order: i32,
+    vertex_positions: Vec<[f32; 2]>,
+}
+
+impl DrawableState {
+    fn new(drawable: &Drawable) -> Self {
+        Self {
+            visible: drawable.dynamic_flags.contains(DynamicFlags::IS_VISIBLE),
+            opacity: drawable.opacity,
+            draw_order: drawable.draw_order,
+            render_order: drawable.render_order,
+            vertex_positions: drawable.vertex_positions.to_vec(),
+        }
+    }
+}
+
+/// Finds what changed about every drawable between two calls to `update`.
+///
+/// Cubism resets the `*_DID_CHANGE` dynamic flags while updating the model, so they cannot be
+/// read afterwards. Comparing against the last seen state also catches changes from several
+/// model updates between two draws.
+#[derive(Default)]
+pub struct DrawableTracker {
+    states: Vec<DrawableState>,
+}
+
+impl DrawableTracker {
+    /// Compares the drawables against the last call and remembers their new state. Everything
+    /// counts as changed on the first call, after `clear`, or if the number of drawables changed.
+    pub fn update(&mut self, drawables: &[Drawable]) -> Vec<DrawableChanges> {
+        if self.states.len() != drawables.len() {
+            self.states = drawables.iter().map(DrawableState::new).collect();
+            return vec![DrawableChanges::ALL; drawables.len()];
+        }
+
+        drawables
+            .iter()
+            .zip(self.states.iter_mut())
+            .map(|(drawable, state)| {
+                let visible = drawable.dynamic_flags.contains(DynamicFlags::IS_VISIBLE);
+                let changes = DrawableChanges {
+                    visibility: visible != state.visible,
+                    opacity: drawable.opacity != state.opacity,
+                    draw_order: drawable.draw_order != state.draw_order,
+                    render_order: drawable.render_order != state.render_order,
+                    vertex_positions: drawable.vertex_positions
+                        != state.vertex_positions.as_slice(),
+                };
+
+                state.visible = visible;
+                state.opacity = drawable.opacity;
+                state.draw_order = drawable.draw_order;
+                state.render_order = drawable.render_order;
+                if changes.vertex_positions {
+                    state.vertex_positions.clear();
+                    state
+                        .vertex_positions
+                        .extend_from_slice(drawable.vertex_positions);
+                }
+
+                changes
+            })
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+}
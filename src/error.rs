@@ -0,0 +1,93 @@
+use gdnative::{core_types::GodotError, prelude::Dictionary};
+use std::{
+    fmt::{self, Display},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// The part of model loading that failed.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoadStage {
+    Model3,
+    Moc,
+    Expression,
+    Pose,
+    Physics,
+    UserData,
+    Motion,
+    Texture,
+}
+
+impl LoadStage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoadStage::Model3 => "model3",
+            LoadStage::Moc => "moc",
+            LoadStage::Expression => "expression",
+            LoadStage::Pose => "pose",
+            LoadStage::Physics => "physics",
+            LoadStage::UserData => "user_data",
+            LoadStage::Motion => "motion",
+            LoadStage::Texture => "texture",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LoadError {
+    pub stage: LoadStage,
+    pub file: PathBuf,
+    pub code: GodotError,
+    pub message: String,
+}
+
+impl LoadError {
+    /// The file could not be opened or read.
+    pub fn io(stage: LoadStage, file: &Path, err: io::Error) -> Self {
+        Self {
+            stage,
+            file: file.to_path_buf(),
+            code: match err.kind() {
+                io::ErrorKind::NotFound => GodotError::FileNotFound,
+                io::ErrorKind::PermissionDenied => GodotError::FileNoPermission,
+                _ => GodotError::FileCantOpen,
+            },
+            message: err.to_string(),
+        }
+    }
+
+    /// The file was read but its contents could not be parsed.
+    pub fn parse<E: Display>(stage: LoadStage, file: &Path, err: E) -> Self {
+        Self {
+            stage,
+            file: file.to_path_buf(),
+            code: GodotError::ParseError,
+            message: err.to_string(),
+        }
+    }
+
+    pub fn to_dict(&self) -> Dictionary {
+        let d = Dictionary::new();
+
+        d.insert("error", self.code as i64);
+        d.insert("stage", self.stage.as_str());
+        d.insert("file", self.file.to_str().unwrap_or("invalid"));
+        d.insert("message", self.message.clone());
+
+        d.into_shared()
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Unable to load {} file {}: {}",
+            self.stage.as_str(),
+            self.file.display(),
+            self.message
+        )
+    }
+}
+
+impl std::error::Error for LoadError {}
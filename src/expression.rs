@@ -0,0 +1,404 @@
+use cubism::json::expression::{Expression3, ExpressionBlendType};
+use gdnative::core_types::GodotError;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+use crate::ids::ModelIds;
+use crate::motion::easing_sine;
+
+pub fn blend_type_name(blend_type: ExpressionBlendType) -> &'static str {
+    match blend_type {
+        ExpressionBlendType::Add => "Add",
+        ExpressionBlendType::Multiply => "Multiply",
+        ExpressionBlendType::Overwrite => "Overwrite",
+    }
+}
+
+pub fn blend_type_from_name(name: &str) -> Option<ExpressionBlendType> {
+    match name {
+        "Add" => Some(ExpressionBlendType::Add),
+        "Multiply" => Some(ExpressionBlendType::Multiply),
+        "Overwrite" => Some(ExpressionBlendType::Overwrite),
+        _ => None,
+    }
+}
+
+/// Serializable form of an exp3 file.
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Exp3File {
+    #[serde(rename = "Type")]
+    pub ty: String,
+    pub fade_in_time: f32,
+    pub fade_out_time: f32,
+    pub parameters: Vec<Exp3FileParameter>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Exp3FileParameter {
+    pub id: String,
+    pub value: f32,
+    pub blend: &'static str,
+}
+
+impl Exp3File {
+    pub fn new(fade_in_time: f32, fade_out_time: f32) -> Self {
+        Self {
+            ty: "Live2D Expression".to_string(),
+            fade_in_time,
+            fade_out_time,
+            parameters: vec![],
+        }
+    }
+
+    pub fn from_expression3(exp3: &Expression3) -> Self {
+        Self {
+            ty: exp3.ty.to_string(),
+            fade_in_time: exp3.fade_in_time,
+            fade_out_time: exp3.fade_out_time,
+            parameters: exp3
+                .parameters
+                .iter()
+                .map(|p| Exp3FileParameter {
+                    id: p.id.to_string(),
+                    value: p.value,
+                    blend: blend_type_name(p.blend_type),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec_pretty(self)
+    }
+
+    /// Round-trips through json so the result is exactly what loading the file would give.
+    pub fn to_expression3(&self) -> serde_json::Result<Expression3> {
+        Expression3::from_reader(self.to_bytes()?.as_slice())
+    }
+}
+
+/// Adds or updates the expression in the `FileReferences.Expressions` of a model3 file.
+/// Every other key keeps its place, as serde_json is built with `preserve_order`.
+pub fn add_expression_reference(
+    model3: &[u8],
+    name: &str,
+    file: &str,
+) -> Result<Vec<u8>, GodotError> {
+    let mut model3: Value = serde_json::from_slice(model3).map_err(|_| GodotError::ParseError)?;
+
+    let expressions = model3
+        .get_mut("FileReferences")
+        .and_then(Value::as_object_mut)
+        .ok_or(GodotError::ParseError)?
+        .entry("Expressions")
+        .or_insert_with(|| Value::Array(vec![]))
+        .as_array_mut()
+        .ok_or(GodotError::ParseError)?;
+
+    match expressions
+        .iter_mut()
+        .find(|e| e.get("Name").and_then(Value::as_str) == Some(name))
+    {
+        Some(expression) => expression["File"] = Value::from(file),
+        None => expressions.push(json!({ "Name": name, "File": file })),
+    }
+
+    serde_json::to_vec_pretty(&model3).map_err(|_| GodotError::ParseError)
+}
+
+struct ExpressionValue {
+    parameter: usize,
+    blend_type: ExpressionBlendType,
+    value: f32,
+}
+
+/// An exp3 file with its parameter ids resolved against the model.
+struct ExpressionData {
+    values: Vec<ExpressionValue>,
+    fade_in_time: f32,
+    fade_out_time: f32,
+}
+
+impl ExpressionData {
+    fn new(exp3: &Expression3, ids: &ModelIds) -> Self {
+        Self {
+            values: exp3
+                .parameters
+                .iter()
+                .filter_map(|p| {
+                    Some(ExpressionValue {
+                        parameter: ids.parameter(&p.id)?,
+                        blend_type: p.blend_type,
+                        value: p.value,
+                    })
+                })
+                .collect(),
+            fade_in_time: exp3.fade_in_time.max(0.0),
+            fade_out_time: exp3.fade_out_time.max(0.0),
+        }
+    }
+}
+
+struct ActiveExpression {
+    name: String,
+    weight: f32,
+    elapsed: f32,
+    /// Elapsed time at which the expression started fading out.
+    fade_out_start: Option<f32>,
+}
+
+/// Blends any number of expressions onto the model, each with its own weight and fades.
+#[derive(Default)]
+pub struct ExpressionManager {
+    expressions: HashMap<String, ExpressionData>,
+    active: Vec<ActiveExpression>,
+    /// Per parameter (overwrite, additive, multiply) accumulators, reused every update.
+    blend: Vec<(f32, f32, f32)>,
+}
+
+impl ExpressionManager {
+    pub fn new<'a>(
+        expression3s: impl IntoIterator<Item = (&'a String, &'a Expression3)>,
+        ids: &ModelIds,
+    ) -> Self {
+        Self {
+            expressions: expression3s
+                .into_iter()
+                .map(|(name, exp3)| (name.clone(), ExpressionData::new(exp3, ids)))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    /// Registers an expression, replacing any expression with the same name.
+    pub fn insert(&mut self, name: &str, exp3: &Expression3, ids: &ModelIds) {
+        self.expressions
+            .insert(name.to_string(), ExpressionData::new(exp3, ids));
+    }
+
+    fn fade_weight(&self, active: &ActiveExpression) -> f32 {
+        let data = &self.expressions[&active.name];
+
+        let fade_in = if data.fade_in_time > 0.0 {
+            easing_sine(active.elapsed / data.fade_in_time)
+        } else {
+            1.0
+        };
+        let fade_out = match active.fade_out_start {
+            Some(start) if data.fade_out_time > 0.0 => {
+                easing_sine(1.0 - (active.elapsed - start) / data.fade_out_time)
+            }
+            Some(_) => 0.0,
+            None => 1.0,
+        };
+
+        fade_in * fade_out
+    }
+
+    /// Fades in the expression with the given weight, or changes its weight if it is
+    /// already active.
+    pub fn add(&mut self, name: &str, weight: f32) -> Result<(), GodotError> {
+        if !self.expressions.contains_key(name) {
+            return Err(GodotError::DoesNotExist);
+        }
+
+        match self
+            .active
+            .iter_mut()
+            .find(|a| a.name == name && a.fade_out_start.is_none())
+        {
+            Some(active) => active.weight = weight,
+            None => self.active.push(ActiveExpression {
+                name: name.to_string(),
+                weight,
+                elapsed: 0.0,
+                fade_out_start: None,
+            }),
+        }
+
+        Ok(())
+    }
+
+    /// Fades out the expression.
+    pub fn remove(&mut self, name: &str) -> Result<(), GodotError> {
+        if !self.expressions.contains_key(name) {
+            return Err(GodotError::DoesNotExist);
+        }
+
+        for active in self.active.iter_mut().filter(|a| a.name == name) {
+            if active.fade_out_start.is_none() {
+                active.fade_out_start = Some(active.elapsed);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Changes the weight of an active expression.
+    pub fn set_weight(&mut self, name: &str, weight: f32) -> Result<(), GodotError> {
+        match self
+            .active
+            .iter_mut()
+            .find(|a| a.name == name && a.fade_out_start.is_none())
+        {
+            Some(active) => {
+                active.weight = weight;
+                Ok(())
+            }
+            None if self.expressions.contains_key(name) => Err(GodotError::Unavailable),
+            None => Err(GodotError::DoesNotExist),
+        }
+    }
+
+    /// Fades out every active expression.
+    pub fn clear(&mut self) {
+        for active in self.active.iter_mut() {
+            if active.fade_out_start.is_none() {
+                active.fade_out_start = Some(active.elapsed);
+            }
+        }
+    }
+
+    /// Name and current weight, including fades, of every active expression.
+    pub fn active(&self) -> impl Iterator<Item = (&str, f32)> {
+        self.active
+            .iter()
+            .map(move |a| (a.name.as_str(), a.weight * self.fade_weight(a)))
+    }
+
+    /// Advances the fades and blends the active expressions onto the model's parameter values,
+    /// which must be the base values for this frame.
+    pub fn update(&mut self, delta: f32, parameter_values: &mut [f32]) {
+        for active in self.active.iter_mut() {
+            active.elapsed += delta;
+        }
+
+        let expressions = &self.expressions;
+        self.active.retain(|active| match active.fade_out_start {
+            Some(start) => active.elapsed - start < expressions[&active.name].fade_out_time,
+            None => true,
+        });
+
+        if self.active.is_empty() {
+            return;
+        }
+
+        self.blend.clear();
+        self.blend
+            .extend(parameter_values.iter().map(|value| (*value, 0.0, 1.0)));
+
+        for active in self.active.iter() {
+            let weight = active.weight * self.fade_weight(active);
+
+            for v in self.expressions[&active.name].values.iter() {
+                let (overwrite, additive, multiply) = &mut self.blend[v.parameter];
+                match v.blend_type {
+                    ExpressionBlendType::Add => *additive += v.value * weight,
+                    ExpressionBlendType::Multiply => *multiply *= 1.0 + (v.value - 1.0) * weight,
+                    ExpressionBlendType::Overwrite => *overwrite += (v.value - *overwrite) * weight,
+                }
+            }
+        }
+
+        for (value, (overwrite, additive, multiply)) in
+            parameter_values.iter_mut().zip(self.blend.iter())
+        {
+            *value = (overwrite + additive) * multiply;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parameters `A`, `B` and `C`, with an expression `e` changing each of them.
+    fn manager(
+        fade_in_time: f32,
+        fade_out_time: f32,
+        values: [(f32, &'static str); 3],
+    ) -> ExpressionManager {
+        let mut exp3 = Exp3File::new(fade_in_time, fade_out_time);
+        exp3.parameters = ["A", "B", "C"]
+            .iter()
+            .zip(values.iter())
+            .map(|(id, (value, blend))| Exp3FileParameter {
+                id: id.to_string(),
+                value: *value,
+                blend: *blend,
+            })
+            .collect();
+
+        let ids = ModelIds::from_ids(&["A", "B", "C"], &[] as &[&str]);
+        let mut manager = ExpressionManager::default();
+        manager.insert("e", &exp3.to_expression3().unwrap(), &ids);
+        manager
+    }
+
+    fn update(manager: &mut ExpressionManager, delta: f32, base: [f32; 3]) -> [f32; 3] {
+        let mut values = base;
+        manager.update(delta, &mut values);
+        values
+    }
+
+    fn assert_near(actual: [f32; 3], expected: [f32; 3]) {
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((a - e).abs() < 0.0001, "{:?} is not {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn blend_types_combine_with_the_base_values() {
+        let mut manager = manager(
+            0.0,
+            0.0,
+            [(0.5, "Add"), (2.0, "Multiply"), (10.0, "Overwrite")],
+        );
+        manager.add("e", 1.0).unwrap();
+
+        assert_near(update(&mut manager, 0.0, [1.0, 2.0, 3.0]), [1.5, 4.0, 10.0]);
+    }
+
+    #[test]
+    fn weights_scale_every_blend_type() {
+        let mut manager = manager(
+            0.0,
+            0.0,
+            [(0.5, "Add"), (2.0, "Multiply"), (10.0, "Overwrite")],
+        );
+        manager.add("e", 0.5).unwrap();
+
+        assert_near(update(&mut manager, 0.0, [1.0, 2.0, 3.0]), [1.25, 3.0, 6.5]);
+    }
+
+    #[test]
+    fn expressions_fade_in() {
+        let mut manager = manager(
+            1.0,
+            1.0,
+            [(1.0, "Add"), (3.0, "Multiply"), (4.0, "Overwrite")],
+        );
+        manager.add("e", 1.0).unwrap();
+
+        // easing_sine(0.5) is 0.5
+        assert_near(update(&mut manager, 0.5, [0.0, 1.0, 0.0]), [0.5, 2.0, 2.0]);
+        assert_near(update(&mut manager, 0.5, [0.0, 1.0, 0.0]), [1.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn faded_out_expressions_are_removed() {
+        let mut manager = manager(0.0, 1.0, [(1.0, "Add"), (1.0, "Multiply"), (0.0, "Add")]);
+        manager.add("e", 1.0).unwrap();
+        update(&mut manager, 1.0, [0.0; 3]);
+
+        manager.remove("e").unwrap();
+        assert_near(update(&mut manager, 0.5, [0.0; 3]), [0.5, 0.0, 0.0]);
+        assert_eq!(manager.active().count(), 1);
+
+        assert_near(update(&mut manager, 0.6, [0.0; 3]), [0.0; 3]);
+        assert_eq!(manager.active().count(), 0);
+    }
+}
@@ -0,0 +1,44 @@
+use gdnative::{api::Mesh, prelude::*};
+
+/// Vertex positions in Cubism's y up model space.
+pub fn vertex_positions(positions: &[[f32; 2]]) -> Vector2Array {
+    positions.iter().map(|v| Vector2::new(v[0], v[1])).collect()
+}
+
+/// Vertex uvs with v pointing up, as stored by Cubism.
+pub fn vertex_uvs(uvs: &[[f32; 2]]) -> Vector2Array {
+    uvs.iter().map(|uv| Vector2::new(uv[0], uv[1])).collect()
+}
+
+pub fn indices(indices: &[u16]) -> Int32Array {
+    indices.iter().map(|i| *i as i32).collect()
+}
+
+/// Arrays for `ArrayMesh.add_surface_from_arrays` with `PRIMITIVE_TRIANGLES`, converted to
+/// Godot's y down pixels and uvs.
+pub fn surface_arrays(
+    positions: &[[f32; 2]],
+    uvs: &[[f32; 2]],
+    drawable_indices: &[u16],
+    ppu: f32,
+) -> VariantArray {
+    let arrays = VariantArray::new();
+    arrays.resize(Mesh::ARRAY_MAX as i32);
+
+    arrays.set(
+        Mesh::ARRAY_VERTEX as i32,
+        positions
+            .iter()
+            .map(|v| Vector2::new(v[0] * ppu, -v[1] * ppu))
+            .collect::<Vector2Array>(),
+    );
+    arrays.set(
+        Mesh::ARRAY_TEX_UV as i32,
+        uvs.iter()
+            .map(|uv| Vector2::new(uv[0], 1.0 - uv[1]))
+            .collect::<Vector2Array>(),
+    );
+    arrays.set(Mesh::ARRAY_INDEX as i32, indices(drawable_indices));
+
+    arrays.into_shared()
+}
@@ -0,0 +1,41 @@
+use cubism::core::Model;
+use std::collections::HashMap;
+
+/// Maps parameter and part ids to their index in the model, built once at load.
+#[derive(Default)]
+pub struct ModelIds {
+    parameters: HashMap<String, usize>,
+    parts: HashMap<String, usize>,
+}
+
+impl ModelIds {
+    pub fn new(model: &Model) -> Self {
+        let moc = model.moc();
+
+        Self::from_ids(moc.parameter_ids(), moc.part_ids())
+    }
+
+    /// Builds the maps from the parameter and part ids in index order.
+    pub fn from_ids(parameter_ids: &[impl AsRef<str>], part_ids: &[impl AsRef<str>]) -> Self {
+        Self {
+            parameters: parameter_ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (id.as_ref().to_string(), i))
+                .collect(),
+            parts: part_ids
+                .iter()
+                .enumerate()
+                .map(|(i, id)| (id.as_ref().to_string(), i))
+                .collect(),
+        }
+    }
+
+    pub fn parameter(&self, id: &str) -> Option<usize> {
+        self.parameters.get(id).copied()
+    }
+
+    pub fn part(&self, id: &str) -> Option<usize> {
+        self.parts.get(id).copied()
+    }
+}
@@ -1,11 +1,30 @@
 use gdnative::prelude::{godot_init, InitHandle};
 
+mod changes;
 mod dict_helpers;
+mod error;
+mod expression;
+mod geometry;
+mod ids;
 mod loader;
+mod motion;
+mod physics;
+mod pose;
+pub mod rasterizer;
+mod reader;
+mod render;
+mod renderer_2d;
+mod renderer_3d;
+mod snapshot;
+mod texture;
+mod viewport;
 
 fn init(handle: InitHandle) {
     handle.add_class::<loader::CubismModel>();
     handle.add_class::<loader::CubismModelFactory>();
+    handle.add_class::<renderer_2d::CubismRenderer2D>();
+    handle.add_class::<renderer_3d::CubismRenderer3D>();
+    handle.add_class::<viewport::CubismViewport>();
 }
 
 godot_init!(init);
@@ -1,9 +1,8 @@
 use cubism::{
-    core::{Drawable, Parameter, Part},
-    expression::Expression,
+    core::{Drawable, Moc, Model, Parameter, Part},
     json::{
         expression::{Expression3, ExpressionBlendType, ExpressionParameter},
-        model::{GroupTarget, Model3, Motions},
+        model::{GroupTarget, Model3, Motion},
         motion::Motion3,
         physics::Physics3,
         pose::Pose3,
@@ -11,192 +10,456 @@ use cubism::{
     },
     model::UserModel,
 };
-use gdnative::prelude::*;
-use std::{collections::HashMap, fs::File, path::PathBuf};
+use gdnative::{
+    api::{Engine, Image, Texture},
+    core_types::GodotError,
+    prelude::*,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use crate::changes::{DrawableChanges, DrawableTracker};
 use crate::dict_helpers::*;
+use crate::error::{LoadError, LoadStage};
+use crate::expression::{
+    add_expression_reference, blend_type_from_name, blend_type_name, Exp3File, Exp3FileParameter,
+    ExpressionManager,
+};
+use crate::geometry;
+use crate::ids::ModelIds;
+use crate::motion::{MotionEntry, MotionEvent, MotionManager, MotionPriority, MotionRequest};
+use crate::physics::Physics;
+use crate::pose::Pose;
+use crate::rasterizer::{rasterize, RasterCanvas, RasterDrawable, RasterTexture};
+use crate::reader::{reader_for, writer_for, ModelReader};
+use crate::snapshot::{diff, Snapshot};
+use crate::texture::{create_texture, decode_png, texture_pixels, TextureOptions};
+
+/// `Model3` only knows about a fixed set of motion groups, so the groups are
+/// read separately from the same file.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MotionGroups3 {
+    file_references: MotionReferences,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct MotionReferences {
+    #[serde(default)]
+    motions: HashMap<String, Vec<Motion>>,
+}
+
+/// Groups that `motions()` and `json()` always reported, kept so existing scripts still find them.
+const LEGACY_MOTION_GROUPS: [&str; 6] = [
+    "idle",
+    "tap_body",
+    "pinch_in",
+    "pinch_out",
+    "shake",
+    "flick_head",
+];
+
+/// Converts a model3 motion group name such as `TapBody` into the snake_case key
+/// used by `motions()` and `json()`, `tap_body`.
+fn motion_group_key(group: &str) -> String {
+    let mut key = String::with_capacity(group.len() + 4);
+    let mut previous_lowercase = false;
+
+    for c in group.chars() {
+        if c.is_uppercase() {
+            if previous_lowercase {
+                key.push('_');
+            }
+            key.extend(c.to_lowercase());
+            previous_lowercase = false;
+        } else {
+            key.push(c);
+            previous_lowercase = c.is_lowercase() || c.is_ascii_digit();
+        }
+    }
+
+    key
+}
 
-#[derive(Default)]
-struct MotionData {
-    idle: Vec<Motion3>,
-    tap_body: Vec<Motion3>,
-    pinch_in: Vec<Motion3>,
-    pinch_out: Vec<Motion3>,
-    shake: Vec<Motion3>,
-    flick_head: Vec<Motion3>,
+fn read_file(
+    reader: &dyn ModelReader,
+    stage: LoadStage,
+    path: &Path,
+) -> Result<Vec<u8>, LoadError> {
+    reader.read(path).map_err(|e| LoadError::io(stage, path, e))
 }
 
-#[derive(NativeClass, Copy, Clone, Default)]
+/// Loads a texture through the reader if it can, or else reads and decodes it as a PNG.
+fn load_texture(reader: &dyn ModelReader, path: &Path) -> Result<RasterTexture, LoadError> {
+    if let Some(texture) = reader.read_texture(path) {
+        return Ok(texture);
+    }
+
+    decode_png(&read_file(reader, LoadStage::Texture, path)?).map_err(|e| {
+        LoadError::parse(
+            LoadStage::Texture,
+            path,
+            format!("Unable to decode image: {}", e),
+        )
+    })
+}
+
+fn load_motion_group(
+    reader: &dyn ModelReader,
+    res_path: &Path,
+    motions: Vec<Motion>,
+) -> Result<Vec<MotionEntry>, LoadError> {
+    motions
+        .into_iter()
+        .map(|motion| {
+            let path = res_path.join(&motion.file);
+            let motion3 =
+                Motion3::from_reader(read_file(reader, LoadStage::Motion, &path)?.as_slice())
+                    .map_err(|e| LoadError::parse(LoadStage::Motion, &path, e))?;
+
+            Ok(MotionEntry {
+                motion,
+                motion3: Arc::new(motion3),
+            })
+        })
+        .collect()
+}
+
+/// Converts a result into a Godot `Error` code for GDScript.
+fn result_code(result: Result<(), GodotError>) -> i64 {
+    match result {
+        Ok(()) => 0,
+        Err(e) => e as i64,
+    }
+}
+
+/// Converts an optional priority from GDScript, defaulting to `Normal`. `None` (0) only
+/// reports that nothing is playing, so it is rejected like any other invalid value.
+fn motion_priority(priority: Option<i64>) -> Option<MotionPriority> {
+    match priority {
+        Some(priority) => MotionPriority::from_i64(priority).filter(|p| *p != MotionPriority::None),
+        None => Some(MotionPriority::Normal),
+    }
+}
+
+#[derive(NativeClass, Default)]
 #[user_data(MutexData<CubismModelFactory>)]
 #[inherit(Reference)]
-pub struct CubismModelFactory;
+pub struct CubismModelFactory {
+    last_error: Option<LoadError>,
+    texture_options: TextureOptions,
+}
 
 #[methods]
 impl CubismModelFactory {
     fn new(_owner: &Reference) -> Self {
-        Self
+        Self::default()
     }
 
+    /// Loads a model, returning `null` on failure. The reason for the failure
+    /// can be retrieved with `last_error`.
+    ///
+    /// `res://` and `user://` paths are read through Godot, everything else is read
+    /// from the OS filesystem.
     #[export]
-    pub fn cubism_model(
-        &self,
-        _owner: &Reference,
-        path: String,
-        file_name: String,
-    ) -> Instance<CubismModel, Unique> {
+    pub fn cubism_model(&mut self, _owner: &Reference, path: String, file_name: String) -> Variant {
         let res_path = PathBuf::from(path);
 
-        let json3 = Model3::from_reader(
-            File::open(&res_path.join(file_name)).expect("Unable to open file"),
-        )
-        .expect("Unable to read model3 json");
+        match Self::load(
+            reader_for(&res_path),
+            res_path.clone(),
+            &file_name,
+            &self.texture_options,
+        ) {
+            Ok(model) => {
+                self.last_error = None;
+                model.emplace().owned_to_variant()
+            }
+            Err(e) => {
+                godot_error!("{}", e);
+                self.last_error = Some(e);
+                Variant::new()
+            }
+        }
+    }
+
+    /// Sets how textures are created for models loaded afterwards. Filtering and mipmaps
+    /// are enabled and alpha is left straight by default.
+    #[export]
+    pub fn set_texture_options(
+        &mut self,
+        _owner: &Reference,
+        filter: bool,
+        mipmaps: bool,
+        premultiply_alpha: bool,
+    ) {
+        self.texture_options = TextureOptions {
+            filter,
+            mipmaps,
+            premultiply_alpha,
+        };
+    }
+
+    /// The Godot `Error` code of the last failed load, or `OK`.
+    #[export]
+    pub fn last_error_code(&self, _owner: &Reference) -> i64 {
+        match &self.last_error {
+            Some(e) => e.code as i64,
+            None => 0,
+        }
+    }
+
+    /// A description of the last failed load. Empty if the last load succeeded.
+    #[export]
+    pub fn last_error(&self, _owner: &Reference) -> Dictionary {
+        match &self.last_error {
+            Some(e) => e.to_dict(),
+            None => Dictionary::new_shared(),
+        }
+    }
 
-        let model = UserModel::from_model3(&res_path, &json3).expect("Unable to user model file");
+    /// Loads a model with every file read through the given `reader`. Only needs Godot if the
+    /// reader does, the textures are only decoded and are created on first use.
+    pub fn load(
+        reader: &dyn ModelReader,
+        res_path: PathBuf,
+        file_name: &str,
+        texture_options: &TextureOptions,
+    ) -> Result<CubismModel, LoadError> {
+        let model3_path = res_path.join(file_name);
+        let model3_bytes = read_file(reader, LoadStage::Model3, &model3_path)?;
+        let json3 = Model3::from_reader(model3_bytes.as_slice())
+            .map_err(|e| LoadError::parse(LoadStage::Model3, &model3_path, e))?;
+        let motion_groups3: MotionGroups3 = serde_json::from_slice(&model3_bytes)
+            .map_err(|e| LoadError::parse(LoadStage::Model3, &model3_path, e))?;
+
+        let moc_path = match &json3.file_references.moc {
+            Some(moc_path) => res_path.join(moc_path),
+            None => {
+                return Err(LoadError::parse(
+                    LoadStage::Model3,
+                    &model3_path,
+                    "model3 has no Moc file reference",
+                ))
+            }
+        };
+        let moc = Moc::from_bytes(&read_file(reader, LoadStage::Moc, &moc_path)?)
+            .map_err(|e| LoadError::parse(LoadStage::Moc, &moc_path, e))?;
+        let mut model = UserModel::from_model(Model::from_moc(&moc));
 
         let mut expression3s = HashMap::new();
-        let mut expressions = HashMap::new();
         for exp in json3.file_references.expressions.iter() {
-            expression3s.insert(
-                exp.name.to_string(),
-                Expression3::from_reader(
-                    File::open(&res_path.join(&exp.file)).expect("Unable to open file"),
-                )
-                .ok(),
-            );
-            expressions.insert(
-                exp.name.to_string(),
-                Expression::from_exp3_json(&model, &res_path.join(&exp.file)).unwrap(),
-            );
+            let exp_path = res_path.join(&exp.file);
+            let exp3 = Expression3::from_reader(
+                read_file(reader, LoadStage::Expression, &exp_path)?.as_slice(),
+            )
+            .map_err(|e| LoadError::parse(LoadStage::Expression, &exp_path, e))?;
+
+            expression3s.insert(exp.name.to_string(), exp3);
         }
 
         let mut pose3 = None;
         if let Some(pose_path) = &json3.file_references.pose {
-            pose3 = Pose3::from_reader(
-                File::open(&res_path.join(pose_path)).expect("Unable to open file"),
-            )
-            .ok();
+            let pose_path = res_path.join(pose_path);
+            pose3 = Some(
+                Pose3::from_reader(read_file(reader, LoadStage::Pose, &pose_path)?.as_slice())
+                    .map_err(|e| LoadError::parse(LoadStage::Pose, &pose_path, e))?,
+            );
         }
 
         let mut physics3 = None;
         if let Some(physics_path) = &json3.file_references.physics {
-            physics3 = Physics3::from_reader(
-                File::open(&res_path.join(physics_path)).expect("Unable to open file"),
-            )
-            .ok();
+            let physics_path = res_path.join(physics_path);
+            physics3 = Some(
+                Physics3::from_reader(
+                    read_file(reader, LoadStage::Physics, &physics_path)?.as_slice(),
+                )
+                .map_err(|e| LoadError::parse(LoadStage::Physics, &physics_path, e))?,
+            );
         }
 
         let mut user_data3 = None;
         if let Some(user_data_path) = &json3.file_references.user_data {
-            user_data3 = UserData3::from_reader(
-                File::open(&res_path.join(user_data_path)).expect("Unable to open file"),
-            )
-            .ok();
+            let user_data_path = res_path.join(user_data_path);
+            user_data3 = Some(
+                UserData3::from_reader(
+                    read_file(reader, LoadStage::UserData, &user_data_path)?.as_slice(),
+                )
+                .map_err(|e| LoadError::parse(LoadStage::UserData, &user_data_path, e))?,
+            );
         }
 
-        let mut motion3s = MotionData::default();
-        let motion_files = &json3.file_references.motions;
-        // TODO abstract out the file reading stuff
-        motion3s.idle = motion_files
-            .idle
-            .iter()
-            .map(|x| {
-                Motion3::from_reader(
-                    File::open(&res_path.join(&x.file)).expect("Unable to open idle motion file"),
-                )
-                .expect("Unable to read idle motion file")
-            })
-            .collect();
-        motion3s.tap_body = motion_files
-            .tap_body
-            .iter()
-            .map(|x| {
-                Motion3::from_reader(
-                    File::open(&res_path.join(&x.file)).expect("Unable to open idle motion file"),
-                )
-                .expect("Unable to read idle motion file")
-            })
-            .collect();
-        motion3s.pinch_in = motion_files
-            .pinch_in
-            .iter()
-            .map(|x| {
-                Motion3::from_reader(
-                    File::open(&res_path.join(&x.file)).expect("Unable to open idle motion file"),
-                )
-                .expect("Unable to read idle motion file")
-            })
-            .collect();
-        motion3s.pinch_out = motion_files
-            .pinch_out
-            .iter()
-            .map(|x| {
-                Motion3::from_reader(
-                    File::open(&res_path.join(&x.file)).expect("Unable to open idle motion file"),
-                )
-                .expect("Unable to read idle motion file")
-            })
-            .collect();
-        motion3s.shake = motion_files
-            .shake
-            .iter()
-            .map(|x| {
-                Motion3::from_reader(
-                    File::open(&res_path.join(&x.file)).expect("Unable to open idle motion file"),
-                )
-                .expect("Unable to read idle motion file")
-            })
-            .collect();
-        motion3s.flick_head = motion_files
-            .flick_head
-            .iter()
-            .map(|x| {
-                Motion3::from_reader(
-                    File::open(&res_path.join(&x.file)).expect("Unable to open idle motion file"),
-                )
-                .expect("Unable to read idle motion file")
-            })
-            .collect();
+        let mut images = vec![];
+        for texture_path in json3.file_references.textures.iter() {
+            let texture_path = res_path.join(texture_path);
+            images.push(load_texture(reader, &texture_path)?);
+        }
+
+        let mut motion3s = HashMap::new();
+        for (group, motions) in motion_groups3.file_references.motions {
+            motion3s.insert(group, load_motion_group(reader, &res_path, motions)?);
+        }
 
-        CubismModel {
+        let ids = ModelIds::new(model.model());
+        let expressions = ExpressionManager::new(&expression3s, &ids);
+        let physics = physics3
+            .as_ref()
+            .map(|physics3| Physics::new(physics3, model.model(), &ids));
+        let pose = pose3.as_ref().map(|pose3| Pose::new(pose3, &ids));
+        if let Some(pose) = &pose {
+            pose.reset(model.model_mut());
+        }
+
+        Ok(CubismModel {
             res_path,
+            model3_path,
+            ids,
+            base_parameters: model.model().parameter_values().to_vec(),
             model,
             json: json3,
+            images,
+            texture_options: *texture_options,
+            textures: vec![],
 
             expression3s: expression3s,
             expressions: expressions,
 
             pose3s: pose3,
-            _physics3s: physics3,
+            pose,
+            physics,
+            physics_enabled: true,
+            hidden_parts: HashMap::new(),
             user_data3s: user_data3,
+            snapshots: HashMap::new(),
             motion3s: motion3s,
-        }
-        .emplace()
+
+            motion_manager: MotionManager::default(),
+            idle_motion_group: "Idle".to_string(),
+            last_update_frame: None,
+            drawable_tracker: DrawableTracker::default(),
+            drawable_changes: vec![],
+        })
     }
 }
 
 #[derive(NativeClass)]
 #[inherit(Reference)]
 #[no_constructor]
+#[register_with(Self::register_signals)]
 #[user_data(user_data::MutexData<CubismModel>)]
 pub struct CubismModel {
     res_path: PathBuf, // This might be a relative path?
+    model3_path: PathBuf,
     model: UserModel,
+    ids: ModelIds,
+    /// Parameter values as set through `set_parameter` and friends, `reset_parameters` and
+    /// `restore_snapshot`. Restored at the start of every update so that motions, expressions
+    /// and physics are applied to the same base each frame and never accumulate.
+    base_parameters: Vec<f32>,
     json: Model3,
+    /// Decoded pixels of every texture, kept in sync with `textures`.
+    images: Vec<RasterTexture>,
+    texture_options: TextureOptions,
+    /// Godot textures, created from `images` by `create_textures`.
+    textures: Vec<Ref<Texture>>,
 
-    expression3s: HashMap<String, Option<Expression3>>,
-    expressions: HashMap<String, Expression>,
+    expression3s: HashMap<String, Expression3>,
+    expressions: ExpressionManager,
 
     pose3s: Option<Pose3>,
-    _physics3s: Option<Physics3>,
+    pose: Option<Pose>,
+    physics: Option<Physics>,
+    physics_enabled: bool,
+    /// Parts forced to be invisible and their real opacity, only zeroed while the model
+    /// updates its drawables so motions and pose keep working on the real value.
+    hidden_parts: HashMap<usize, f32>,
     user_data3s: Option<UserData3>,
-    motion3s: MotionData,
+    snapshots: HashMap<String, Snapshot>,
+    motion3s: HashMap<String, Vec<MotionEntry>>,
+
+    motion_manager: MotionManager,
+    idle_motion_group: String,
+    /// Godot idle frame of the last `advance_frame`, so that renderers sharing the model
+    /// advance it only once per frame.
+    last_update_frame: Option<i64>,
+    drawable_tracker: DrawableTracker,
+    /// What changed about every drawable in the last `update`.
+    drawable_changes: Vec<DrawableChanges>,
 }
 
 unsafe impl Sync for CubismModel {}
 unsafe impl Send for CubismModel {}
 
+impl CubismModel {
+    pub fn user_model(&self) -> &UserModel {
+        &self.model
+    }
+
+    /// Creates the Godot textures if they were not created yet.
+    pub fn create_textures(&mut self) {
+        if self.textures.len() != self.images.len() {
+            self.textures = self
+                .images
+                .iter()
+                .map(|image| create_texture(image, &self.texture_options))
+                .collect();
+        }
+    }
+
+    /// The model's textures, in texture index order. Empty until `create_textures` is called.
+    pub fn loaded_textures(&self) -> &[Ref<Texture>] {
+        &self.textures
+    }
+}
+
 #[methods]
 impl CubismModel {
+    /// Motion signals are emitted deferred, once the model is no longer locked, so handlers
+    /// can call back into the model.
+    fn register_signals(builder: &ClassBuilder<Self>) {
+        let motion_args = &[
+            SignalArgument {
+                name: "group",
+                default: Variant::from_str(""),
+                export_info: ExportInfo::new(VariantType::GodotString),
+                usage: PropertyUsage::DEFAULT,
+            },
+            SignalArgument {
+                name: "index",
+                default: Variant::from_i64(0),
+                export_info: ExportInfo::new(VariantType::I64),
+                usage: PropertyUsage::DEFAULT,
+            },
+        ];
+
+        builder.add_signal(Signal {
+            name: "motion_started",
+            args: motion_args,
+        });
+        builder.add_signal(Signal {
+            name: "motion_finished",
+            args: motion_args,
+        });
+        builder.add_signal(Signal {
+            name: "motion_looped",
+            args: motion_args,
+        });
+        builder.add_signal(Signal {
+            name: "motion_event",
+            args: &[SignalArgument {
+                name: "value",
+                default: Variant::from_str(""),
+                export_info: ExportInfo::new(VariantType::GodotString),
+                usage: PropertyUsage::DEFAULT,
+            }],
+        });
+    }
+
     //#region Struct fields
 
     #[export]
@@ -255,55 +518,10 @@ impl CubismModel {
                     })
                     .collect(),
             );
-            d.insert("motions", {
-                let data = &data.motions;
-                let d = Dictionary::new();
-
-                d.insert::<_, Vec<Dictionary>>(
-                    "idle",
-                    data.idle
-                        .iter()
-                        .map(|x| create_dict_from_motion(&x))
-                        .collect(),
-                );
-                d.insert::<_, Vec<Dictionary>>(
-                    "tap_body",
-                    data.tap_body
-                        .iter()
-                        .map(|x| create_dict_from_motion(&x))
-                        .collect(),
-                );
-                d.insert::<_, Vec<Dictionary>>(
-                    "pinch_in",
-                    data.pinch_in
-                        .iter()
-                        .map(|x| create_dict_from_motion(&x))
-                        .collect(),
-                );
-                d.insert::<_, Vec<Dictionary>>(
-                    "pinch_out",
-                    data.pinch_out
-                        .iter()
-                        .map(|x| create_dict_from_motion(&x))
-                        .collect(),
-                );
-                d.insert::<_, Vec<Dictionary>>(
-                    "shake",
-                    data.shake
-                        .iter()
-                        .map(|x| create_dict_from_motion(&x))
-                        .collect(),
-                );
-                d.insert::<_, Vec<Dictionary>>(
-                    "flick_head",
-                    data.flick_head
-                        .iter()
-                        .map(|x| create_dict_from_motion(&x))
-                        .collect(),
-                );
-
-                d.into_shared()
-            });
+            d.insert(
+                "motions",
+                self.motion_dict(|x| create_dict_from_motion(&x.motion)),
+            );
             d.insert(
                 "user_data",
                 data.user_data
@@ -377,38 +595,29 @@ impl CubismModel {
     pub fn expressions(&self, _owner: &Reference) -> VariantArray {
         let va = VariantArray::new();
 
-        for (key, value) in self.expression3s.iter() {
+        for (key, exp) in self.expression3s.iter() {
             let d = Dictionary::new();
 
             d.insert("name", key.to_string());
-            if let Some(exp) = value {
-                d.insert("type", exp.ty.to_string());
-                d.insert("fade_in_time", exp.fade_in_time);
-                d.insert("fade_out_time", exp.fade_out_time);
-                d.insert("parameters", {
-                    let va = VariantArray::new();
-
-                    for p in exp.parameters.iter() {
-                        let d = Dictionary::new();
+            d.insert("type", exp.ty.to_string());
+            d.insert("fade_in_time", exp.fade_in_time);
+            d.insert("fade_out_time", exp.fade_out_time);
+            d.insert("parameters", {
+                let va = VariantArray::new();
 
-                        d.insert("id", p.id.to_string());
-                        d.insert("blend_type", p.blend_type as i32);
-                        d.insert(
-                            "blend_type_string",
-                            match p.blend_type {
-                                ExpressionBlendType::Add => "Add",
-                                ExpressionBlendType::Multiply => "Multiply",
-                                ExpressionBlendType::Overwrite => "Overwrite",
-                            },
-                        );
-                        d.insert("value", p.value);
+                for p in exp.parameters.iter() {
+                    let d = Dictionary::new();
 
-                        va.push(d.into_shared());
-                    }
+                    d.insert("id", p.id.to_string());
+                    d.insert("blend_type", p.blend_type as i32);
+                    d.insert("blend_type_string", blend_type_name(p.blend_type));
+                    d.insert("value", p.value);
 
-                    va.into_shared()
-                });
-            }
+                    va.push(d.into_shared());
+                }
+
+                va.into_shared()
+            });
 
             va.push(d.into_shared());
         }
@@ -495,53 +704,52 @@ impl CubismModel {
         d.into_shared()
     }
 
+    /// Every motion group in name order, keyed by its snake_case name, with the legacy groups
+    /// always present even if the model does not define them.
+    fn motion_dict(&self, f: impl Fn(&MotionEntry) -> Dictionary) -> Dictionary {
+        let d = Dictionary::new();
+
+        for group in LEGACY_MOTION_GROUPS.iter() {
+            d.insert::<_, Vec<Dictionary>>(*group, vec![]);
+        }
+
+        let mut groups: Vec<(&String, &Vec<MotionEntry>)> = self.motion3s.iter().collect();
+        groups.sort_by(|a, b| a.0.cmp(b.0));
+        for (group, entries) in groups {
+            d.insert::<_, Vec<Dictionary>>(
+                motion_group_key(group),
+                entries.iter().map(&f).collect(),
+            );
+        }
+
+        d.into_shared()
+    }
+
+    /// Motions of every group, keyed by the snake_case group name (`TapBody` is `tap_body`).
+    /// Use `motion_groups` for the names `motion_group` and `play_motion` expect.
     #[export]
     pub fn motions(&self, _owner: &Reference) -> Dictionary {
-        let d = Dictionary::new();
+        self.motion_dict(|x| create_dict_from_motion3(&x.motion3))
+    }
 
-        let m = &self.motion3s;
+    /// Names of every motion group as written in the model3 file, sorted.
+    #[export]
+    pub fn motion_groups(&self, _owner: &Reference) -> Vec<String> {
+        let mut groups: Vec<String> = self.motion3s.keys().cloned().collect();
+        groups.sort();
 
-        d.insert::<&str, Vec<Dictionary>>(
-            "idle",
-            m.idle.iter().map(|x| create_dict_from_motion3(x)).collect(),
-        );
-        d.insert::<&str, Vec<Dictionary>>(
-            "tap_body",
-            m.tap_body
-                .iter()
-                .map(|x| create_dict_from_motion3(x))
-                .collect(),
-        );
-        d.insert::<&str, Vec<Dictionary>>(
-            "pinch_in",
-            m.pinch_in
-                .iter()
-                .map(|x| create_dict_from_motion3(x))
-                .collect(),
-        );
-        d.insert::<&str, Vec<Dictionary>>(
-            "pinch_out",
-            m.pinch_out
-                .iter()
-                .map(|x| create_dict_from_motion3(x))
-                .collect(),
-        );
-        d.insert::<&str, Vec<Dictionary>>(
-            "shake",
-            m.shake
-                .iter()
-                .map(|x| create_dict_from_motion3(x))
-                .collect(),
-        );
-        d.insert::<&str, Vec<Dictionary>>(
-            "flick_head",
-            m.flick_head
+        groups
+    }
+
+    #[export]
+    pub fn motion_group(&self, _owner: &Reference, group: String) -> Vec<Dictionary> {
+        match self.motion3s.get(&group) {
+            Some(entries) => entries
                 .iter()
-                .map(|x| create_dict_from_motion3(x))
+                .map(|x| create_dict_from_motion3(&x.motion3))
                 .collect(),
-        );
-
-        d.into_shared()
+            None => vec![],
+        }
     }
 
     //#endregion
@@ -613,6 +821,36 @@ impl CubismModel {
         d.into_shared()
     }
 
+    //#region Textures
+
+    #[export]
+    pub fn textures(&mut self, _owner: &Reference) -> Vec<Ref<Texture>> {
+        self.create_textures();
+        self.textures.clone()
+    }
+
+    #[export]
+    pub fn texture(&mut self, _owner: &Reference, index: i64) -> Option<Ref<Texture>> {
+        self.create_textures();
+        self.textures.get(index as usize).cloned()
+    }
+
+    /// Replaces the texture at `index`, e.g. for outfit swaps. Returns a Godot `Error` code.
+    #[export]
+    pub fn set_texture(&mut self, _owner: &Reference, index: i64, texture: Ref<Texture>) -> i64 {
+        self.create_textures();
+        match self.textures.get_mut(index as usize) {
+            Some(t) => {
+                self.images[index as usize] = texture_pixels(&texture);
+                *t = texture;
+                0
+            }
+            None => GodotError::ParameterRangeError as i64,
+        }
+    }
+
+    //#endregion
+
     //#region Parameters
 
     #[export]
@@ -634,6 +872,179 @@ impl CubismModel {
         a.into_shared()
     }
 
+    /// Applies `f` to the base value of a parameter, clamped to its range.
+    ///
+    /// Working on the base values keeps motions, expressions and physics from being baked in,
+    /// and lets the value persist through every `update`. Motions that animate the parameter
+    /// blend over it while they play and hand back to it once they end.
+    fn write_parameter(
+        &mut self,
+        index: usize,
+        f: impl FnOnce(f32) -> f32,
+    ) -> Result<(), GodotError> {
+        let moc = self.model.model().moc();
+        let (min, max) = match (
+            moc.parameter_min().get(index),
+            moc.parameter_max().get(index),
+        ) {
+            (Some(min), Some(max)) => (*min, *max),
+            _ => return Err(GodotError::ParameterRangeError),
+        };
+
+        let value = f(self.base_parameters[index]).max(min).min(max);
+        self.model.model_mut().parameter_values_mut()[index] = value;
+        self.base_parameters[index] = value;
+
+        Ok(())
+    }
+
+    fn find_parameter(&self, id: &str) -> Result<usize, GodotError> {
+        self.ids.parameter(id).ok_or(GodotError::DoesNotExist)
+    }
+
+    /// Sets a parameter, blending from its current value by `weight` (default 1).
+    /// Returns a Godot `Error` code.
+    #[export]
+    pub fn set_parameter(
+        &mut self,
+        _owner: &Reference,
+        id: String,
+        value: f32,
+        #[opt] weight: Option<f32>,
+    ) -> i64 {
+        let weight = weight.unwrap_or(1.0);
+        result_code(
+            self.find_parameter(&id).and_then(|i| {
+                self.write_parameter(i, |current| current + (value - current) * weight)
+            }),
+        )
+    }
+
+    /// Adds `value * weight` to a parameter. Returns a Godot `Error` code.
+    #[export]
+    pub fn add_parameter(
+        &mut self,
+        _owner: &Reference,
+        id: String,
+        value: f32,
+        #[opt] weight: Option<f32>,
+    ) -> i64 {
+        let weight = weight.unwrap_or(1.0);
+        result_code(
+            self.find_parameter(&id)
+                .and_then(|i| self.write_parameter(i, |current| current + value * weight)),
+        )
+    }
+
+    /// Multiplies a parameter by `value`, scaled towards 1 by `weight`. Returns a Godot `Error` code.
+    #[export]
+    pub fn multiply_parameter(
+        &mut self,
+        _owner: &Reference,
+        id: String,
+        value: f32,
+        #[opt] weight: Option<f32>,
+    ) -> i64 {
+        let weight = weight.unwrap_or(1.0);
+        result_code(self.find_parameter(&id).and_then(|i| {
+            self.write_parameter(i, |current| current * (1.0 + (value - 1.0) * weight))
+        }))
+    }
+
+    /// Index based variant of `set_parameter`.
+    #[export]
+    pub fn set_parameter_by_index(
+        &mut self,
+        _owner: &Reference,
+        index: i64,
+        value: f32,
+        #[opt] weight: Option<f32>,
+    ) -> i64 {
+        let weight = weight.unwrap_or(1.0);
+        result_code(self.write_parameter(index as usize, |current| {
+            current + (value - current) * weight
+        }))
+    }
+
+    /// Index based variant of `add_parameter`.
+    #[export]
+    pub fn add_parameter_by_index(
+        &mut self,
+        _owner: &Reference,
+        index: i64,
+        value: f32,
+        #[opt] weight: Option<f32>,
+    ) -> i64 {
+        let weight = weight.unwrap_or(1.0);
+        result_code(self.write_parameter(index as usize, |current| current + value * weight))
+    }
+
+    /// Index based variant of `multiply_parameter`.
+    #[export]
+    pub fn multiply_parameter_by_index(
+        &mut self,
+        _owner: &Reference,
+        index: i64,
+        value: f32,
+        #[opt] weight: Option<f32>,
+    ) -> i64 {
+        let weight = weight.unwrap_or(1.0);
+        result_code(self.write_parameter(index as usize, |current| {
+            current * (1.0 + (value - 1.0) * weight)
+        }))
+    }
+
+    /// Index of a parameter for the `*_by_index` methods, or -1 if there is no such parameter.
+    #[export]
+    pub fn parameter_index(&self, _owner: &Reference, id: String) -> i64 {
+        self.ids.parameter(&id).map_or(-1, |i| i as i64)
+    }
+
+    /// Current value of every parameter, in index order.
+    #[export]
+    pub fn get_parameter_values(&self, _owner: &Reference) -> Float32Array {
+        Float32Array::from_slice(self.model.model().parameter_values())
+    }
+
+    /// Sets every parameter at once, in index order, clamped to their ranges.
+    /// Returns a Godot `Error` code.
+    #[export]
+    pub fn set_parameter_values(&mut self, _owner: &Reference, values: Float32Array) -> i64 {
+        if values.len() as usize != self.base_parameters.len() {
+            return GodotError::InvalidParameter as i64;
+        }
+
+        let values = values.read();
+        let moc = self.model.model().moc();
+        for (i, (base, value)) in self
+            .base_parameters
+            .iter_mut()
+            .zip(values.iter())
+            .enumerate()
+        {
+            *base = value
+                .max(moc.parameter_min()[i])
+                .min(moc.parameter_max()[i]);
+        }
+        self.model
+            .model_mut()
+            .parameter_values_mut()
+            .copy_from_slice(&self.base_parameters);
+
+        0
+    }
+
+    /// Sets every parameter back to its default value.
+    #[export]
+    pub fn reset_parameters(&mut self, _owner: &Reference) {
+        let defaults = self.model.model().moc().parameter_default().to_vec();
+        self.model
+            .model_mut()
+            .parameter_values_mut()
+            .copy_from_slice(&defaults);
+        self.base_parameters.copy_from_slice(&defaults);
+    }
+
     //#endregion
 
     //#region Parts
@@ -657,6 +1068,76 @@ impl CubismModel {
         a.into_shared()
     }
 
+    /// Sets the opacity of a part, clamped to 0..1. Motions and pose may change it again.
+    /// Returns a Godot `Error` code.
+    #[export]
+    pub fn set_part_opacity(&mut self, _owner: &Reference, id: String, value: f32) -> i64 {
+        match self.ids.part(&id) {
+            Some(part) => {
+                self.model.model_mut().part_opacities_mut()[part] = value.max(0.0).min(1.0);
+                0
+            }
+            None => GodotError::DoesNotExist as i64,
+        }
+    }
+
+    /// Hides or shows a part regardless of what motions and pose do with its opacity.
+    /// Returns a Godot `Error` code.
+    #[export]
+    pub fn set_part_visible(&mut self, _owner: &Reference, id: String, visible: bool) -> i64 {
+        let part = match self.ids.part(&id) {
+            Some(part) => part,
+            None => return GodotError::DoesNotExist as i64,
+        };
+
+        if visible {
+            self.hidden_parts.remove(&part);
+        } else {
+            self.hidden_parts.insert(part, 0.0);
+        }
+
+        0
+    }
+
+    #[export]
+    pub fn is_part_visible(&self, _owner: &Reference, id: String) -> bool {
+        self.ids
+            .part(&id)
+            .map_or(false, |part| !self.hidden_parts.contains_key(&part))
+    }
+
+    /// Ids of every part hidden with `set_part_visible`.
+    #[export]
+    pub fn hidden_parts(&self, _owner: &Reference) -> Vec<String> {
+        let part_ids = self.model.model().moc().part_ids();
+        self.hidden_parts
+            .keys()
+            .map(|part| part_ids[*part].to_string())
+            .collect()
+    }
+
+    /// Shows every part hidden with `set_part_visible`.
+    #[export]
+    pub fn show_all_parts(&mut self, _owner: &Reference) {
+        self.hidden_parts.clear();
+    }
+
+    /// Updates the model's drawables with the hidden parts zeroed, leaving their real opacity
+    /// untouched afterwards.
+    fn update_model(&mut self, delta: f32) {
+        let opacities = self.model.model_mut().part_opacities_mut();
+        for (part, opacity) in self.hidden_parts.iter_mut() {
+            *opacity = std::mem::replace(&mut opacities[*part], 0.0);
+        }
+
+        self.model.update(delta);
+
+        let opacities = self.model.model_mut().part_opacities_mut();
+        for (part, opacity) in self.hidden_parts.iter() {
+            opacities[*part] = *opacity;
+        }
+    }
+
     //#endregion
 
     //#region Drawables
@@ -682,6 +1163,128 @@ impl CubismModel {
 
     //#endregion
 
+    /// Index of the drawable at `index`, if it exists.
+    fn drawable_index(&self, index: i64) -> Option<usize> {
+        if index >= 0 && (index as usize) < self.model.model().moc().drawable_count() {
+            Some(index as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Vertex positions of the drawable at `index`, in Cubism's y up model space.
+    #[export]
+    pub fn drawable_vertex_positions(&self, _owner: &Reference, index: i64) -> Vector2Array {
+        self.drawable_index(index)
+            .map_or_else(Vector2Array::new, |index| {
+                geometry::vertex_positions(self.model.model().drawable_vertex_positions(index))
+            })
+    }
+
+    /// Vertex uvs of the drawable at `index`, with v pointing up.
+    #[export]
+    pub fn drawable_vertex_uvs(&self, _owner: &Reference, index: i64) -> Vector2Array {
+        self.drawable_index(index)
+            .map_or_else(Vector2Array::new, |index| {
+                geometry::vertex_uvs(self.model.model().drawable_vertex_uvs(index))
+            })
+    }
+
+    #[export]
+    pub fn drawable_indices(&self, _owner: &Reference, index: i64) -> Int32Array {
+        self.drawable_index(index)
+            .map_or_else(Int32Array::new, |index| {
+                geometry::indices(self.model.model().moc().drawable_indices()[index])
+            })
+    }
+
+    /// Surface arrays of the drawable at `index` in pixels, ready for
+    /// `ArrayMesh.add_surface_from_arrays(Mesh.PRIMITIVE_TRIANGLES, arrays)`.
+    #[export]
+    pub fn drawable_surface_arrays(&self, _owner: &Reference, index: i64) -> VariantArray {
+        let model = self.model.model();
+        let (_, _, ppu) = model.canvas_info();
+
+        self.drawable_index(index)
+            .map_or_else(VariantArray::new_shared, |index| {
+                geometry::surface_arrays(
+                    model.drawable_vertex_positions(index),
+                    model.drawable_vertex_uvs(index),
+                    model.moc().drawable_indices()[index],
+                    ppu,
+                )
+            })
+    }
+
+    /// Surface arrays of every drawable, in drawable index order. Cheaper than calling
+    /// `drawable_surface_arrays` for each drawable.
+    #[export]
+    pub fn drawables_surface_arrays(&self, _owner: &Reference) -> VariantArray {
+        let (_, _, ppu) = self.model.model().canvas_info();
+        let a = VariantArray::new();
+
+        for drawable in self.model.drawables() {
+            a.push(geometry::surface_arrays(
+                drawable.vertex_positions,
+                drawable.vertex_uvs,
+                drawable.indices,
+                ppu,
+            ));
+        }
+
+        a.into_shared()
+    }
+
+    /// Renders the current state of the model into a `width` by `height` image on the CPU,
+    /// fitting and centering the canvas in it. Nothing is drawn through the `VisualServer`.
+    #[export]
+    pub fn render_to_image(&self, _owner: &Reference, width: i64, height: i64) -> Ref<Image> {
+        let (width, height) = (width.max(1) as usize, height.max(1) as usize);
+        let (size, origin, ppu) = self.model.model().canvas_info();
+
+        let drawables: Vec<Drawable> = self.model.drawables().collect();
+        let raster_drawables: Vec<RasterDrawable> = drawables
+            .iter()
+            .map(RasterDrawable::from_drawable)
+            .collect();
+
+        let pixels = rasterize(
+            &raster_drawables,
+            &self.images,
+            &RasterCanvas { size, origin, ppu },
+            width,
+            height,
+        );
+
+        let image = Image::new();
+        image.create_from_data(
+            width as i64,
+            height as i64,
+            false,
+            Image::FORMAT_RGBA8,
+            ByteArray::from_vec(pixels),
+        );
+        image.into_shared()
+    }
+
+    /// Drawables whose visibility, opacity, draw order, render order or vertex positions
+    /// changed in the last `update`, for exporting only what changed.
+    #[export]
+    pub fn changed_drawables(&self, _owner: &Reference) -> VariantArray {
+        let a = VariantArray::new();
+
+        for (drawable, _) in self
+            .model
+            .drawables()
+            .zip(self.drawable_changes.iter())
+            .filter(|(_, changes)| changes.any())
+        {
+            a.push(create_dict_from_drawable(&drawable));
+        }
+
+        a.into_shared()
+    }
+
     #[export]
     pub fn drawable_opacities(&self, _owner: &Reference) -> VariantArray {
         let mut va = VariantArray::new();
@@ -702,13 +1305,563 @@ impl CubismModel {
         va.into_shared()
     }
 
+    //#region Motions
+
+    /// Emits the motion signals deferred, since they are raised while the model is locked and
+    /// handlers such as one playing the next motion on `motion_finished` call back into it.
+    fn emit_motion_events(&mut self, owner: &Reference) {
+        for event in self.motion_manager.take_events() {
+            let args = match event {
+                MotionEvent::Started { group, index } => vec![
+                    "motion_started".to_variant(),
+                    group.to_variant(),
+                    (index as i64).to_variant(),
+                ],
+                MotionEvent::Finished { group, index } => vec![
+                    "motion_finished".to_variant(),
+                    group.to_variant(),
+                    (index as i64).to_variant(),
+                ],
+                MotionEvent::Looped { group, index } => vec![
+                    "motion_looped".to_variant(),
+                    group.to_variant(),
+                    (index as i64).to_variant(),
+                ],
+                MotionEvent::UserData { value } => {
+                    vec!["motion_event".to_variant(), value.to_variant()]
+                }
+            };
+
+            unsafe { owner.call_deferred("emit_signal", &args) };
+        }
+    }
+
+    /// Starts a motion if `priority` allows it. A `None` index picks a random motion from the group.
+    fn start_motion(
+        &mut self,
+        group: &str,
+        index: Option<usize>,
+        priority: MotionPriority,
+    ) -> bool {
+        let entries = match self.motion3s.get(group) {
+            Some(entries) if !entries.is_empty() => entries,
+            _ => {
+                godot_warn!("No motion group {}", group);
+                return false;
+            }
+        };
+
+        if !self.motion_manager.acquire(priority) {
+            return false;
+        }
+
+        let index = index.unwrap_or_else(|| self.motion_manager.random_index(entries.len()));
+        let entry = match entries.get(index) {
+            Some(entry) => entry,
+            None => {
+                godot_warn!("No motion {} in group {}", index, group);
+                self.motion_manager.cancel_reservation(priority);
+                return false;
+            }
+        };
+
+        self.motion_manager.start(
+            &MotionRequest {
+                group,
+                index,
+                priority,
+            },
+            entry,
+            &self.ids,
+        );
+
+        true
+    }
+
+    /// Starts playing a motion from the given group. Priority is 1 (idle), 2 (normal, the default)
+    /// or 3 (force). Returns `false` if there is no such motion or a motion with the same or
+    /// higher priority is playing or reserved, unless the reservation was made for this priority.
     #[export]
-    pub fn apply_expression(&mut self, _owner: &Reference, expression: String) {
-        self.expressions[&expression].apply(self.model.model_mut(), 1.0);
+    pub fn play_motion(
+        &mut self,
+        owner: &Reference,
+        group: String,
+        index: i64,
+        #[opt] priority: Option<i64>,
+    ) -> bool {
+        let priority = match motion_priority(priority) {
+            Some(priority) => priority,
+            None => {
+                godot_warn!("Invalid motion priority {:?}", priority);
+                return false;
+            }
+        };
+        if index < 0 {
+            godot_warn!("No motion {} in group {}", index, group);
+            return false;
+        }
+
+        let started = self.start_motion(&group, Some(index as usize), priority);
+        self.emit_motion_events(owner);
+
+        started
     }
 
+    /// Same as `play_motion` but picks a random motion from the group.
     #[export]
-    pub fn update(&mut self, _owner: &Reference, delta: f32) {
-        self.model.update(delta);
+    pub fn play_random_motion(
+        &mut self,
+        owner: &Reference,
+        group: String,
+        #[opt] priority: Option<i64>,
+    ) -> bool {
+        let priority = match motion_priority(priority) {
+            Some(priority) => priority,
+            None => {
+                godot_warn!("Invalid motion priority {:?}", priority);
+                return false;
+            }
+        };
+
+        let started = self.start_motion(&group, None, priority);
+        self.emit_motion_events(owner);
+
+        started
+    }
+
+    /// Reserves the next motion slot for `priority`, rejecting requests with the same or lower
+    /// priority until a motion is played with it. Returns `false` if a motion with the same or
+    /// higher priority is playing or reserved.
+    #[export]
+    pub fn reserve_motion(&mut self, _owner: &Reference, priority: i64) -> bool {
+        match motion_priority(Some(priority)) {
+            Some(priority) => self.motion_manager.reserve(priority),
+            None => {
+                godot_warn!("Invalid motion priority {}", priority);
+                false
+            }
+        }
+    }
+
+    #[export]
+    pub fn stop_motion(&mut self, owner: &Reference) {
+        self.motion_manager.stop();
+        self.emit_motion_events(owner);
+    }
+
+    #[export]
+    pub fn is_motion_playing(&self, _owner: &Reference) -> bool {
+        self.motion_manager.is_playing()
+    }
+
+    /// The priority of the motion that is currently playing, 0 if nothing is playing.
+    #[export]
+    pub fn motion_priority(&self, _owner: &Reference) -> i64 {
+        self.motion_manager.current_priority() as i64
+    }
+
+    /// The group a random motion is played from whenever nothing else is playing.
+    /// An empty string disables idle motions.
+    #[export]
+    pub fn set_idle_motion_group(&mut self, _owner: &Reference, group: String) {
+        self.idle_motion_group = group;
+    }
+
+    #[export]
+    pub fn idle_motion_group(&self, _owner: &Reference) -> String {
+        self.idle_motion_group.clone()
+    }
+
+    //#endregion
+
+    //#region Physics
+
+    #[export]
+    pub fn has_physics(&self, _owner: &Reference) -> bool {
+        self.physics.is_some()
+    }
+
+    #[export]
+    pub fn set_physics_enabled(&mut self, _owner: &Reference, enabled: bool) {
+        self.physics_enabled = enabled;
+    }
+
+    #[export]
+    pub fn is_physics_enabled(&self, _owner: &Reference) -> bool {
+        self.physics_enabled
+    }
+
+    /// Puts the physics rig back into its rest position. Use after teleporting the model.
+    #[export]
+    pub fn reset_physics(&mut self, _owner: &Reference) {
+        if let Some(physics) = &mut self.physics {
+            physics.reset();
+        }
+    }
+
+    /// Gravity in the physics3 coordinate system, where `(0, -1)` points down.
+    #[export]
+    pub fn physics_gravity(&self, _owner: &Reference) -> Vector2 {
+        self.physics
+            .as_ref()
+            .map_or(Vector2::new(0.0, 0.0), |physics| physics.gravity())
+    }
+
+    #[export]
+    pub fn set_physics_gravity(&mut self, _owner: &Reference, gravity: Vector2) {
+        if let Some(physics) = &mut self.physics {
+            physics.set_gravity(gravity);
+        }
+    }
+
+    #[export]
+    pub fn physics_wind(&self, _owner: &Reference) -> Vector2 {
+        self.physics
+            .as_ref()
+            .map_or(Vector2::new(0.0, 0.0), |physics| physics.wind())
+    }
+
+    #[export]
+    pub fn set_physics_wind(&mut self, _owner: &Reference, wind: Vector2) {
+        if let Some(physics) = &mut self.physics {
+            physics.set_wind(wind);
+        }
+    }
+
+    #[export]
+    pub fn physics_fps(&self, _owner: &Reference) -> f32 {
+        self.physics.as_ref().map_or(0.0, |physics| physics.fps())
+    }
+
+    /// Runs the simulation at a fixed rate, sub-stepping as needed. 0 steps once per `update`.
+    #[export]
+    pub fn set_physics_fps(&mut self, _owner: &Reference, fps: f32) {
+        if let Some(physics) = &mut self.physics {
+            physics.set_fps(fps);
+        }
+    }
+
+    //#endregion
+
+    //#region Expressions
+
+    /// Fades out every other expression and fades in this one at full weight. Returns a
+    /// Godot `Error` code.
+    #[export]
+    pub fn apply_expression(&mut self, _owner: &Reference, expression: String) -> i64 {
+        if !self.expression3s.contains_key(&expression) {
+            godot_warn!("No expression {}", expression);
+            return GodotError::DoesNotExist as i64;
+        }
+
+        self.expressions.clear();
+        result_code(self.expressions.add(&expression, 1.0))
+    }
+
+    /// Fades in an expression on top of the active ones, or changes its weight if it is
+    /// already active. Returns a Godot `Error` code.
+    #[export]
+    pub fn add_expression(
+        &mut self,
+        _owner: &Reference,
+        expression: String,
+        #[opt] weight: Option<f32>,
+    ) -> i64 {
+        result_code(self.expressions.add(&expression, weight.unwrap_or(1.0)))
+    }
+
+    /// Fades out an expression. Returns a Godot `Error` code.
+    #[export]
+    pub fn remove_expression(&mut self, _owner: &Reference, expression: String) -> i64 {
+        result_code(self.expressions.remove(&expression))
+    }
+
+    /// Changes the weight of an active expression. Returns a Godot `Error` code.
+    #[export]
+    pub fn set_expression_weight(
+        &mut self,
+        _owner: &Reference,
+        expression: String,
+        weight: f32,
+    ) -> i64 {
+        result_code(self.expressions.set_weight(&expression, weight))
+    }
+
+    /// Fades out every active expression.
+    #[export]
+    pub fn clear_expressions(&mut self, _owner: &Reference) {
+        self.expressions.clear();
+    }
+
+    /// Captures the base parameter values, as set through `set_parameter` and friends without
+    /// motions, expressions or physics, as a new expression and registers it under `name`,
+    /// replacing any expression with the same name.
+    ///
+    /// `blend_types` maps parameter ids to `"Add"`, `"Multiply"` or `"Overwrite"`. Parameters
+    /// listed there are always included, every other parameter is included as `"Add"` if it
+    /// differs from its default value. `"Multiply"` is rejected for parameters whose default
+    /// value is 0. Returns a Godot `Error` code.
+    #[export]
+    pub fn create_expression(
+        &mut self,
+        _owner: &Reference,
+        name: String,
+        blend_types: Dictionary,
+        #[opt] fade_in_time: Option<f32>,
+        #[opt] fade_out_time: Option<f32>,
+    ) -> i64 {
+        let mut exp3_file =
+            Exp3File::new(fade_in_time.unwrap_or(1.0), fade_out_time.unwrap_or(1.0));
+
+        let moc = self.model.model().moc();
+        for (i, id) in moc.parameter_ids().iter().enumerate() {
+            let (value, default_value) = (self.base_parameters[i], moc.parameter_default()[i]);
+
+            let blend_type = match blend_types.get(*id).try_to_string() {
+                Some(blend_name) => match blend_type_from_name(&blend_name) {
+                    Some(blend_type) => blend_type,
+                    None => {
+                        godot_warn!("Invalid blend type {} for {}", blend_name, id);
+                        return GodotError::InvalidParameter as i64;
+                    }
+                },
+                None if (value - default_value).abs() > f32::EPSILON => ExpressionBlendType::Add,
+                None => continue,
+            };
+
+            let value = match blend_type {
+                ExpressionBlendType::Add => value - default_value,
+                ExpressionBlendType::Multiply if default_value == 0.0 => {
+                    godot_warn!(
+                        "Unable to multiply {} as its default value is 0, use Add or Overwrite",
+                        id
+                    );
+                    return GodotError::InvalidParameter as i64;
+                }
+                ExpressionBlendType::Multiply => value / default_value,
+                ExpressionBlendType::Overwrite => value,
+            };
+
+            exp3_file.parameters.push(Exp3FileParameter {
+                id: id.to_string(),
+                value,
+                blend: blend_type_name(blend_type),
+            });
+        }
+
+        let exp3 = match exp3_file.to_expression3() {
+            Ok(exp3) => exp3,
+            Err(e) => {
+                godot_error!("Unable to create expression {}: {}", name, e);
+                return GodotError::InvalidData as i64;
+            }
+        };
+
+        self.expressions.insert(&name, &exp3, &self.ids);
+        self.expression3s.insert(name, exp3);
+
+        0
+    }
+
+    /// Writes an expression out as an exp3 file at `file`, relative to the model directory,
+    /// and references it from the model3 file. Returns a Godot `Error` code.
+    #[export]
+    pub fn save_expression(&mut self, _owner: &Reference, name: String, file: String) -> i64 {
+        result_code(self.write_expression(&name, &file))
+    }
+
+    fn write_expression(&mut self, name: &str, file: &str) -> Result<(), GodotError> {
+        let exp3 = self
+            .expression3s
+            .get(name)
+            .ok_or(GodotError::DoesNotExist)?;
+
+        let exp3_bytes = Exp3File::from_expression3(exp3)
+            .to_bytes()
+            .map_err(|_| GodotError::InvalidData)?;
+        let exp3_path = self.res_path.join(file);
+        writer_for(&exp3_path)
+            .write(&exp3_path, &exp3_bytes)
+            .map_err(|e| {
+                godot_error!("Unable to write {}: {}", exp3_path.display(), e);
+                GodotError::FileCantWrite
+            })?;
+
+        let model3_bytes = reader_for(&self.model3_path)
+            .read(&self.model3_path)
+            .map_err(|e| {
+                godot_error!("Unable to read {}: {}", self.model3_path.display(), e);
+                GodotError::FileCantRead
+            })?;
+        let model3_bytes = add_expression_reference(&model3_bytes, name, file)?;
+        writer_for(&self.model3_path)
+            .write(&self.model3_path, &model3_bytes)
+            .map_err(|e| {
+                godot_error!("Unable to write {}: {}", self.model3_path.display(), e);
+                GodotError::FileCantWrite
+            })?;
+
+        self.json =
+            Model3::from_reader(model3_bytes.as_slice()).map_err(|_| GodotError::ParseError)?;
+
+        Ok(())
+    }
+
+    /// The current weight, including fades, of every active expression keyed by name.
+    #[export]
+    pub fn active_expressions(&self, _owner: &Reference) -> Dictionary {
+        let d = Dictionary::new();
+
+        for (name, weight) in self.expressions.active() {
+            d.insert(name, weight);
+        }
+
+        d.into_shared()
+    }
+
+    //#endregion
+
+    //#region Snapshots
+
+    /// Saves the base parameter values and part opacities under `name`, replacing any
+    /// snapshot with the same name.
+    #[export]
+    pub fn capture_snapshot(&mut self, _owner: &Reference, name: String) {
+        let snapshot =
+            Snapshot::capture(&self.base_parameters, self.model.model().part_opacities());
+        self.snapshots.insert(name, snapshot);
+    }
+
+    /// Returns the model to the state saved under `name`. Returns a Godot `Error` code.
+    #[export]
+    pub fn restore_snapshot(&mut self, _owner: &Reference, name: String) -> i64 {
+        let snapshot = match self.snapshots.get(&name) {
+            Some(snapshot) => snapshot,
+            None => return GodotError::DoesNotExist as i64,
+        };
+
+        snapshot.restore(
+            &mut self.base_parameters,
+            self.model.model_mut().part_opacities_mut(),
+        );
+        self.model
+            .model_mut()
+            .parameter_values_mut()
+            .copy_from_slice(&self.base_parameters);
+
+        0
+    }
+
+    /// Differences between the snapshot `name` and the snapshot `other`, or the current state
+    /// if `other` is not given, as `{ "parameters": { id: difference }, "parts": { id: difference } }`.
+    /// Only values that changed are included, and the dictionary is empty if a snapshot does not exist.
+    #[export]
+    pub fn diff_snapshot(
+        &self,
+        _owner: &Reference,
+        name: String,
+        #[opt] other: Option<String>,
+    ) -> Dictionary {
+        let from = match self.snapshots.get(&name) {
+            Some(snapshot) => snapshot,
+            None => return Dictionary::new_shared(),
+        };
+        let (to_parameters, to_part_opacities) = match &other {
+            Some(other) => match self.snapshots.get(other) {
+                Some(snapshot) => (snapshot.parameters(), snapshot.part_opacities()),
+                None => return Dictionary::new_shared(),
+            },
+            None => (
+                self.base_parameters.as_slice(),
+                self.model.model().part_opacities(),
+            ),
+        };
+
+        let moc = self.model.model().moc();
+        let d = Dictionary::new();
+
+        d.insert("parameters", {
+            let d = Dictionary::new();
+
+            for (i, difference) in diff(from.parameters(), to_parameters) {
+                d.insert(moc.parameter_ids()[i], difference);
+            }
+
+            d.into_shared()
+        });
+        d.insert("parts", {
+            let d = Dictionary::new();
+
+            for (i, difference) in diff(from.part_opacities(), to_part_opacities) {
+                d.insert(moc.part_ids()[i], difference);
+            }
+
+            d.into_shared()
+        });
+
+        d.into_shared()
+    }
+
+    #[export]
+    pub fn remove_snapshot(&mut self, _owner: &Reference, name: String) -> i64 {
+        match self.snapshots.remove(&name) {
+            Some(_) => 0,
+            None => GodotError::DoesNotExist as i64,
+        }
+    }
+
+    #[export]
+    pub fn snapshots(&self, _owner: &Reference) -> Vec<String> {
+        self.snapshots.keys().cloned().collect()
+    }
+
+    //#endregion
+
+    /// Advances the model by `delta` seconds, like `update`, unless `advance_frame` already
+    /// did during this idle frame. Renderers use it so that a model shared by several of them
+    /// runs at normal speed.
+    #[export]
+    pub fn advance_frame(&mut self, owner: &Reference, delta: f32) {
+        let frame = Engine::godot_singleton().get_idle_frames();
+        if self.last_update_frame == Some(frame) {
+            return;
+        }
+        self.last_update_frame = Some(frame);
+
+        self.update(owner, delta);
+    }
+
+    /// Advances the model by `delta` seconds.
+    #[export]
+    pub fn update(&mut self, owner: &Reference, delta: f32) {
+        let has_idle_motions = self
+            .motion3s
+            .get(&self.idle_motion_group)
+            .map_or(false, |entries| !entries.is_empty());
+        if !self.motion_manager.is_playing() && has_idle_motions {
+            let group = self.idle_motion_group.clone();
+            self.start_motion(&group, None, MotionPriority::Idle);
+        }
+        self.motion_manager
+            .update(delta, self.model.model_mut(), &self.base_parameters);
+        self.emit_motion_events(owner);
+
+        self.expressions
+            .update(delta, self.model.model_mut().parameter_values_mut());
+
+        if let Some(physics) = &mut self.physics {
+            if self.physics_enabled {
+                physics.evaluate(self.model.model_mut(), delta);
+            }
+        }
+
+        if let Some(pose) = &self.pose {
+            pose.update(self.model.model_mut(), delta);
+        }
+
+        self.update_model(delta);
+
+        let drawables: Vec<Drawable> = self.model.drawables().collect();
+        self.drawable_changes = self.drawable_tracker.update(&drawables);
     }
 }
@@ -0,0 +1,808 @@
+use cubism::{
+    core::Model,
+    json::{
+        model::Motion,
+        motion::{Curve, CurveTarget, Motion3, Segment, SegmentPoint},
+    },
+};
+use std::{
+    f32::consts::PI,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::ids::ModelIds;
+
+const EPSILON: f32 = 0.00001;
+
+/// A motion as referenced from the model3 file alongside its parsed motion3 file.
+pub struct MotionEntry {
+    pub motion: Motion,
+    pub motion3: Arc<Motion3>,
+}
+
+/// Which motion to start, and at which priority.
+pub struct MotionRequest<'a> {
+    pub group: &'a str,
+    pub index: usize,
+    pub priority: MotionPriority,
+}
+
+//#region Curve evaluation
+
+fn lerp_points(a: &SegmentPoint, b: &SegmentPoint, t: f32) -> SegmentPoint {
+    SegmentPoint {
+        time: a.time + (b.time - a.time) * t,
+        value: a.value + (b.value - a.value) * t,
+    }
+}
+
+fn segment_start_time(segment: &Segment) -> f32 {
+    match segment {
+        Segment::Linear(a, _) => a.time,
+        Segment::Bezier(p) => p[0].time,
+        Segment::Stepped(a, _) => a.time,
+        Segment::InverseStepped(start, _) => *start,
+    }
+}
+
+fn segment_end_time(segment: &Segment) -> f32 {
+    match segment {
+        Segment::Linear(_, b) => b.time,
+        Segment::Bezier(p) => p[3].time,
+        Segment::Stepped(_, end) => *end,
+        Segment::InverseStepped(_, b) => b.time,
+    }
+}
+
+fn segment_start_value(segment: &Segment) -> f32 {
+    match segment {
+        Segment::Linear(a, _) => a.value,
+        Segment::Bezier(p) => p[0].value,
+        Segment::Stepped(a, _) => a.value,
+        Segment::InverseStepped(_, b) => b.value,
+    }
+}
+
+fn segment_end_value(segment: &Segment) -> f32 {
+    match segment {
+        Segment::Linear(_, b) => b.value,
+        Segment::Bezier(p) => p[3].value,
+        Segment::Stepped(a, _) => a.value,
+        Segment::InverseStepped(_, b) => b.value,
+    }
+}
+
+fn quadratic_root(a: f32, b: f32, c: f32) -> f32 {
+    if a.abs() < EPSILON {
+        if b.abs() < EPSILON {
+            return -c;
+        }
+        return -c / b;
+    }
+
+    -(b + (b * b - 4.0 * a * c).sqrt()) / (2.0 * a)
+}
+
+/// Finds the root of `a*t^3 + b*t^2 + c*t + d` that lies in `[0, 1]`.
+fn cardano_root(a: f32, b: f32, c: f32, d: f32) -> f32 {
+    if a.abs() < EPSILON {
+        return quadratic_root(b, c, d).clamp(0.0, 1.0);
+    }
+
+    let ba = b / a;
+    let ca = c / a;
+    let da = d / a;
+
+    let p = (3.0 * ca - ba * ba) / 3.0;
+    let p3 = p / 3.0;
+    let q = (2.0 * ba * ba * ba - 9.0 * ba * ca + 27.0 * da) / 27.0;
+    let q2 = q / 2.0;
+    let discriminant = q2 * q2 + p3 * p3 * p3;
+
+    let center = 0.5;
+    let threshold = center + 0.01;
+    let in_range = |root: f32| (root - center).abs() < threshold;
+
+    if discriminant < 0.0 {
+        let mp3 = -p / 3.0;
+        let r = (mp3 * mp3 * mp3).sqrt();
+        let phi = (-q / (2.0 * r)).clamp(-1.0, 1.0).acos();
+        let t1 = 2.0 * r.cbrt();
+
+        let root1 = t1 * (phi / 3.0).cos() - ba / 3.0;
+        if in_range(root1) {
+            return root1.clamp(0.0, 1.0);
+        }
+
+        let root2 = t1 * ((phi + 2.0 * PI) / 3.0).cos() - ba / 3.0;
+        if in_range(root2) {
+            return root2.clamp(0.0, 1.0);
+        }
+
+        let root3 = t1 * ((phi + 4.0 * PI) / 3.0).cos() - ba / 3.0;
+        return root3.clamp(0.0, 1.0);
+    }
+
+    if discriminant == 0.0 {
+        let u1 = if q2 < 0.0 { (-q2).cbrt() } else { -q2.cbrt() };
+
+        let root1 = 2.0 * u1 - ba / 3.0;
+        if in_range(root1) {
+            return root1.clamp(0.0, 1.0);
+        }
+
+        let root2 = -u1 - ba / 3.0;
+        return root2.clamp(0.0, 1.0);
+    }
+
+    let sd = discriminant.sqrt();
+    let u1 = (sd - q2).cbrt();
+    let v1 = (sd + q2).cbrt();
+
+    (u1 - v1 - ba / 3.0).clamp(0.0, 1.0)
+}
+
+fn evaluate_bezier(p: &[SegmentPoint; 4], time: f32, restricted_beziers: bool) -> f32 {
+    let t = if restricted_beziers {
+        // Control points are constrained so that time is linear in t
+        let duration = p[3].time - p[0].time;
+        if duration.abs() < EPSILON {
+            0.0
+        } else {
+            ((time - p[0].time) / duration).clamp(0.0, 1.0)
+        }
+    } else {
+        let x1 = p[0].time;
+        let cx1 = p[1].time;
+        let cx2 = p[2].time;
+        let x2 = p[3].time;
+
+        cardano_root(
+            x2 - 3.0 * cx2 + 3.0 * cx1 - x1,
+            3.0 * cx2 - 6.0 * cx1 + 3.0 * x1,
+            3.0 * cx1 - 3.0 * x1,
+            x1 - time,
+        )
+    };
+
+    let p01 = lerp_points(&p[0], &p[1], t);
+    let p12 = lerp_points(&p[1], &p[2], t);
+    let p23 = lerp_points(&p[2], &p[3], t);
+
+    let p012 = lerp_points(&p01, &p12, t);
+    let p123 = lerp_points(&p12, &p23, t);
+
+    lerp_points(&p012, &p123, t).value
+}
+
+fn evaluate_segment(segment: &Segment, time: f32, restricted_beziers: bool) -> f32 {
+    match segment {
+        Segment::Linear(a, b) => {
+            let duration = b.time - a.time;
+            if duration.abs() < EPSILON {
+                return b.value;
+            }
+
+            let t = ((time - a.time) / duration).max(0.0);
+            a.value + (b.value - a.value) * t
+        }
+        Segment::Bezier(p) => evaluate_bezier(p, time, restricted_beziers),
+        Segment::Stepped(a, _) => a.value,
+        Segment::InverseStepped(_, b) => b.value,
+    }
+}
+
+/// Evaluates the curve at `time` seconds. Returns `None` for a curve without segments.
+pub fn evaluate_curve(curve: &Curve, time: f32, restricted_beziers: bool) -> Option<f32> {
+    let first = curve.segments.first()?;
+    if time <= segment_start_time(first) {
+        return Some(segment_start_value(first));
+    }
+
+    for segment in curve.segments.iter() {
+        if time <= segment_end_time(segment) {
+            return Some(evaluate_segment(segment, time, restricted_beziers));
+        }
+    }
+
+    curve.segments.last().map(segment_end_value)
+}
+
+/// Wraps `time` around the duration of a looped motion, or holds it at the end of any other
+/// motion. Also returns whether the time wrapped.
+fn wrap_time(time: f32, duration: f32, looped: bool) -> (f32, bool) {
+    if time < duration {
+        (time, false)
+    } else if looped && duration > 0.0 {
+        (time % duration, true)
+    } else {
+        // Hold the final pose for this frame, then finish
+        (duration, false)
+    }
+}
+
+//#endregion
+
+/// Where the output of a curve is written to, resolved once when a motion starts.
+#[derive(Copy, Clone)]
+enum CurveBinding {
+    Parameter(usize),
+    PartOpacity(usize),
+    /// Model curves (eye blink, lip sync, opacity) and ids missing from the model.
+    Unbound,
+}
+
+/// Eases `0..1` with a sine curve, matching the Cubism framework's fades.
+pub fn easing_sine(value: f32) -> f32 {
+    if value <= 0.0 {
+        0.0
+    } else if value >= 1.0 {
+        1.0
+    } else {
+        0.5 - 0.5 * (value * PI).cos()
+    }
+}
+
+/// Weight of a fade that started `elapsed` seconds ago and lasts `duration` seconds.
+fn fade_weight(elapsed: f32, duration: f32) -> f32 {
+    if duration <= 0.0 {
+        1.0
+    } else {
+        easing_sine(elapsed / duration)
+    }
+}
+
+/// Something that happened during playback, to be reported to Godot as a signal.
+pub enum MotionEvent {
+    Started {
+        group: String,
+        index: usize,
+    },
+    Finished {
+        group: String,
+        index: usize,
+    },
+    Looped {
+        group: String,
+        index: usize,
+    },
+    /// Playback crossed a user data entry in the motion3 file.
+    UserData {
+        value: String,
+    },
+}
+
+struct PlayingMotion {
+    group: String,
+    index: usize,
+    motion3: Arc<Motion3>,
+    bindings: Vec<CurveBinding>,
+    fade_in_time: f32,
+    fade_out_time: f32,
+    /// Position in the motion, wraps around for looped motions.
+    time: f32,
+    /// Total time since the motion started.
+    elapsed: f32,
+    /// Set once a newer motion replaces this one, the elapsed time at which the fade out started.
+    fade_out_start: Option<f32>,
+    /// Motion time of the last user data check, starts below 0 so events at 0 are not skipped.
+    last_event_time: f32,
+}
+
+impl PlayingMotion {
+    /// Seconds left before the motion has completely faded out, if it is going to end.
+    fn remaining(&self) -> Option<f32> {
+        let meta = &self.motion3.meta;
+        let natural_end = if meta.looped {
+            None
+        } else {
+            Some(meta.duration - self.time)
+        };
+        let fade_end = self
+            .fade_out_start
+            .map(|start| start + self.fade_out_time - self.elapsed);
+
+        match (natural_end, fade_end) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(self.remaining(), Some(remaining) if remaining <= 0.0)
+    }
+
+    fn fade_in_weight(&self, fade_in_time: f32) -> f32 {
+        fade_weight(self.elapsed, fade_in_time)
+    }
+
+    fn fade_out_weight(&self, fade_out_time: f32) -> f32 {
+        match self.remaining() {
+            Some(remaining) if fade_out_time > 0.0 => easing_sine(remaining / fade_out_time),
+            _ => 1.0,
+        }
+    }
+
+    fn finished_event(&self) -> MotionEvent {
+        MotionEvent::Finished {
+            group: self.group.clone(),
+            index: self.index,
+        }
+    }
+}
+
+/// Plays motions, cross-fading from the previous motion whenever a new one starts.
+#[derive(Default)]
+struct MotionPlayer {
+    queue: Vec<PlayingMotion>,
+    events: Vec<MotionEvent>,
+}
+
+impl MotionPlayer {
+    /// Starts a motion, fading out everything that was playing before.
+    fn play(&mut self, group: &str, index: usize, entry: &MotionEntry, ids: &ModelIds) {
+        let motion3 = entry.motion3.clone();
+        for playing in self.queue.iter_mut() {
+            if playing.fade_out_start.is_none() {
+                playing.fade_out_start = Some(playing.elapsed);
+            }
+        }
+        self.remove_finished();
+
+        let bindings = motion3
+            .curves
+            .iter()
+            .map(|c| match c.target {
+                CurveTarget::Parameter => ids
+                    .parameter(&c.id)
+                    .map_or(CurveBinding::Unbound, CurveBinding::Parameter),
+                CurveTarget::PartOpacity => ids
+                    .part(&c.id)
+                    .map_or(CurveBinding::Unbound, CurveBinding::PartOpacity),
+                CurveTarget::Model => CurveBinding::Unbound,
+            })
+            .collect();
+
+        self.events.push(MotionEvent::Started {
+            group: group.to_string(),
+            index,
+        });
+        self.queue.push(PlayingMotion {
+            group: group.to_string(),
+            index,
+            motion3,
+            bindings,
+            fade_in_time: entry.motion.fade_in_time.max(0.0),
+            fade_out_time: entry.motion.fade_out_time.max(0.0),
+            time: 0.0,
+            elapsed: 0.0,
+            fade_out_start: None,
+            last_event_time: -1.0,
+        });
+    }
+
+    fn stop(&mut self) {
+        for playing in self.queue.drain(..) {
+            self.events.push(playing.finished_event());
+        }
+    }
+
+    fn remove_finished(&mut self) {
+        let events = &mut self.events;
+        self.queue.retain(|playing| {
+            if playing.is_finished() {
+                events.push(playing.finished_event());
+                false
+            } else {
+                true
+            }
+        });
+    }
+
+    fn is_playing(&self) -> bool {
+        !self.queue.is_empty()
+    }
+
+    /// Advances every motion by `delta` seconds, reporting the user data and loops crossed.
+    fn advance(&mut self, delta: f32) {
+        for playing in self.queue.iter_mut() {
+            let meta = playing.motion3.meta;
+
+            playing.elapsed += delta;
+            let (time, looped) = wrap_time(playing.time + delta, meta.duration, meta.looped);
+            playing.time = time;
+
+            let (from, to) = (playing.last_event_time, playing.time);
+            for user_data in playing.motion3.user_data.iter() {
+                let crossed = if looped {
+                    user_data.time > from || user_data.time <= to
+                } else {
+                    user_data.time > from && user_data.time <= to
+                };
+                if crossed {
+                    self.events.push(MotionEvent::UserData {
+                        value: user_data.value.clone(),
+                    });
+                }
+            }
+            playing.last_event_time = to;
+
+            if looped {
+                self.events.push(MotionEvent::Looped {
+                    group: playing.group.clone(),
+                    index: playing.index,
+                });
+            }
+        }
+    }
+
+    /// Blends the curve values onto the model, oldest motion first so that newer motions fade
+    /// in over older ones.
+    ///
+    /// The model's parameters must hold their pre-motion values for this frame. Fades blend
+    /// from those rather than from the last frame's output, so their progress only depends on
+    /// the fade times, and a motion faded out to 0 leaves the pre-motion value untouched.
+    fn apply(&self, model: &mut Model) {
+        for playing in self.queue.iter() {
+            let meta = playing.motion3.meta;
+
+            for (curve, binding) in playing.motion3.curves.iter().zip(playing.bindings.iter()) {
+                let value = match evaluate_curve(curve, playing.time, meta.restricted_beziers) {
+                    Some(value) => value,
+                    None => continue,
+                };
+
+                match *binding {
+                    CurveBinding::Parameter(i) => {
+                        // Curves may override the fade times of the motion
+                        let weight = playing
+                            .fade_in_weight(curve.fade_in_time.unwrap_or(playing.fade_in_time))
+                            * playing.fade_out_weight(
+                                curve.fade_out_time.unwrap_or(playing.fade_out_time),
+                            );
+
+                        let source = model.parameter_values()[i];
+                        model.parameter_values_mut()[i] = source + (value - source) * weight;
+                    }
+                    CurveBinding::PartOpacity(i) => model.part_opacities_mut()[i] = value,
+                    CurveBinding::Unbound => {}
+                }
+            }
+        }
+    }
+}
+
+/// Motion priorities, matching the Cubism framework.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MotionPriority {
+    None = 0,
+    Idle = 1,
+    Normal = 2,
+    Force = 3,
+}
+
+impl MotionPriority {
+    pub fn from_i64(value: i64) -> Option<Self> {
+        match value {
+            0 => Some(MotionPriority::None),
+            1 => Some(MotionPriority::Idle),
+            2 => Some(MotionPriority::Normal),
+            3 => Some(MotionPriority::Force),
+            _ => None,
+        }
+    }
+}
+
+/// Decides which motion requests are allowed to play based on their priority.
+///
+/// A request must first reserve its priority, which fails while a motion with the
+/// same or higher priority is playing or reserved. `Force` requests always succeed.
+/// A slot can be reserved ahead of time, e.g. while waiting for a reaction to load, and is
+/// then used up by the next motion started with that priority.
+pub struct MotionManager {
+    player: MotionPlayer,
+    current_priority: MotionPriority,
+    reserve_priority: MotionPriority,
+    rng_state: u32,
+}
+
+impl Default for MotionManager {
+    fn default() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or_default();
+
+        Self {
+            player: MotionPlayer::default(),
+            current_priority: MotionPriority::None,
+            reserve_priority: MotionPriority::None,
+            rng_state: seed | 1,
+        }
+    }
+}
+
+impl MotionManager {
+    /// Reserves the next motion slot for `priority`. Returns `false` if a motion with
+    /// the same or higher priority is already playing or reserved.
+    pub fn reserve(&mut self, priority: MotionPriority) -> bool {
+        if priority == MotionPriority::Force {
+            self.reserve_priority = priority;
+            return true;
+        }
+
+        if priority <= self.reserve_priority || priority <= self.current_priority {
+            return false;
+        }
+
+        self.reserve_priority = priority;
+        true
+    }
+
+    /// Takes the slot for a motion about to start at `priority`, using up a matching
+    /// reservation made earlier or reserving the slot now.
+    pub fn acquire(&mut self, priority: MotionPriority) -> bool {
+        if priority != MotionPriority::None && priority == self.reserve_priority {
+            return true;
+        }
+
+        self.reserve(priority)
+    }
+
+    /// Releases a reservation that will not be used.
+    pub fn cancel_reservation(&mut self, priority: MotionPriority) {
+        if self.reserve_priority == priority {
+            self.reserve_priority = MotionPriority::None;
+        }
+    }
+
+    /// Starts the requested motion, consuming the reservation for its priority.
+    pub fn start(&mut self, request: &MotionRequest, entry: &MotionEntry, ids: &ModelIds) {
+        self.cancel_reservation(request.priority);
+        self.current_priority = request.priority;
+
+        self.player.play(request.group, request.index, entry, ids);
+    }
+
+    pub fn stop(&mut self) {
+        self.player.stop();
+        self.current_priority = MotionPriority::None;
+        self.reserve_priority = MotionPriority::None;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.player.is_playing()
+    }
+
+    /// Takes every event that happened since the last call.
+    pub fn take_events(&mut self) -> Vec<MotionEvent> {
+        std::mem::take(&mut self.player.events)
+    }
+
+    pub fn current_priority(&self) -> MotionPriority {
+        self.current_priority
+    }
+
+    /// Picks an index in `0..len` for random motions. `len` must not be 0.
+    pub fn random_index(&mut self, len: usize) -> usize {
+        // xorshift32
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng_state = x;
+
+        x as usize % len
+    }
+
+    /// Loads the pre-motion parameter values for this frame into the model, like the
+    /// framework's `LoadParameters`, then blends the playing motions over them.
+    pub fn update(&mut self, delta: f32, model: &mut Model, base_parameters: &[f32]) {
+        model
+            .parameter_values_mut()
+            .copy_from_slice(base_parameters);
+        self.player.advance(delta);
+        self.player.apply(model);
+        self.remove_finished();
+    }
+
+    /// Drops the motions that finished, after they were applied one last time, and releases
+    /// the current priority once nothing is playing.
+    fn remove_finished(&mut self) {
+        self.player.remove_finished();
+        if !self.player.is_playing() {
+            self.current_priority = MotionPriority::None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A motion with a single parameter curve made of `segments`, in motion3 encoding.
+    fn motion3(segments: &str, duration: f32, looped: bool, restricted_beziers: bool) -> Motion3 {
+        let json = format!(
+            r#"{{
+                "Version": 3,
+                "Meta": {{
+                    "Duration": {duration},
+                    "Fps": 30.0,
+                    "Loop": {looped},
+                    "AreBeziersRestricted": {restricted_beziers},
+                    "CurveCount": 1,
+                    "TotalSegmentCount": 1,
+                    "TotalPointCount": 4,
+                    "UserDataCount": 0,
+                    "TotalUserDataSize": 0
+                }},
+                "Curves": [{{ "Target": "Parameter", "Id": "ParamAngleX", "Segments": [{segments}] }}],
+                "UserData": []
+            }}"#
+        );
+
+        Motion3::from_reader(json.as_bytes()).unwrap()
+    }
+
+    fn value_at(motion3: &Motion3, time: f32) -> f32 {
+        evaluate_curve(&motion3.curves[0], time, motion3.meta.restricted_beziers).unwrap()
+    }
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 0.0001,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn linear_segments_interpolate() {
+        let motion3 = motion3("0, 0, 0, 1, 1, 0, 2, 3", 2.0, false, true);
+
+        assert_near(value_at(&motion3, 0.5), 0.5);
+        assert_near(value_at(&motion3, 1.5), 2.0);
+    }
+
+    #[test]
+    fn restricted_beziers_are_linear_in_time() {
+        let motion3 = motion3("0, 0, 1, 0.5, 0, 0.5, 1, 1, 1", 1.0, false, true);
+
+        assert_near(value_at(&motion3, 0.5), 0.5);
+        assert_near(value_at(&motion3, 0.296875), 0.212074);
+    }
+
+    #[test]
+    fn unrestricted_beziers_solve_for_time() {
+        // x(t) = 1.5t - 1.5t^2 + t^3, so time 0.296875 is at t = 0.25
+        let motion3 = motion3("0, 0, 1, 0.5, 0, 0.5, 1, 1, 1", 1.0, false, false);
+
+        assert_near(value_at(&motion3, 0.5), 0.5);
+        assert_near(value_at(&motion3, 0.296875), 0.15625);
+    }
+
+    #[test]
+    fn stepped_segments_hold_the_first_value() {
+        let motion3 = motion3("0, 1, 2, 1, 5", 1.0, false, true);
+
+        assert_near(value_at(&motion3, 0.0), 1.0);
+        assert_near(value_at(&motion3, 0.99), 1.0);
+    }
+
+    #[test]
+    fn inverse_stepped_segments_jump_to_the_last_value() {
+        let motion3 = motion3("0, 1, 3, 1, 5", 1.0, false, true);
+
+        assert_near(value_at(&motion3, 0.01), 5.0);
+        assert_near(value_at(&motion3, 1.0), 5.0);
+    }
+
+    #[test]
+    fn times_outside_the_curve_hold_the_end_values() {
+        let motion3 = motion3("1, 2, 0, 2, 4", 3.0, false, true);
+
+        assert_near(value_at(&motion3, 0.0), 2.0);
+        assert_near(value_at(&motion3, 0.5), 2.0);
+        assert_near(value_at(&motion3, 3.0), 4.0);
+    }
+
+    #[test]
+    fn looped_motions_wrap_around() {
+        let motion3 = motion3("0, 0, 0, 2, 2", 2.0, true, true);
+        let meta = motion3.meta;
+
+        let (time, looped) = wrap_time(2.5, meta.duration, meta.looped);
+        assert!(looped);
+        assert_near(time, 0.5);
+        assert_near(value_at(&motion3, time), 0.5);
+
+        assert_eq!(wrap_time(1.5, meta.duration, meta.looped), (1.5, false));
+        assert_eq!(wrap_time(2.5, meta.duration, false), (2.0, false));
+    }
+
+    fn entry(duration: f32) -> MotionEntry {
+        MotionEntry {
+            motion: serde_json::from_str(
+                r#"{ "File": "test.motion3.json", "FadeInTime": 0.0, "FadeOutTime": 0.0 }"#,
+            )
+            .unwrap(),
+            motion3: Arc::new(motion3("0, 0, 0, 1, 1", duration, false, true)),
+        }
+    }
+
+    /// Starts a one second motion the way the model does, if `priority` allows it.
+    fn play(manager: &mut MotionManager, priority: MotionPriority) -> bool {
+        if !manager.acquire(priority) {
+            return false;
+        }
+
+        let request = MotionRequest {
+            group: "Test",
+            index: 0,
+            priority,
+        };
+        manager.start(&request, &entry(1.0), &ModelIds::default());
+        true
+    }
+
+    #[test]
+    fn lower_priorities_are_rejected_while_a_higher_one_plays() {
+        let mut manager = MotionManager::default();
+        assert!(play(&mut manager, MotionPriority::Normal));
+
+        assert!(!manager.reserve(MotionPriority::Idle));
+        assert!(!manager.reserve(MotionPriority::Normal));
+        assert!(!play(&mut manager, MotionPriority::Idle));
+        assert_eq!(manager.current_priority(), MotionPriority::Normal);
+    }
+
+    #[test]
+    fn lower_priorities_are_rejected_while_a_higher_one_is_reserved() {
+        let mut manager = MotionManager::default();
+        assert!(manager.reserve(MotionPriority::Normal));
+
+        assert!(!manager.reserve(MotionPriority::Idle));
+        assert!(!play(&mut manager, MotionPriority::Idle));
+    }
+
+    #[test]
+    fn reservations_are_used_up_by_the_matching_start() {
+        let mut manager = MotionManager::default();
+        assert!(manager.reserve(MotionPriority::Normal));
+
+        assert!(play(&mut manager, MotionPriority::Normal));
+        assert_eq!(manager.reserve_priority, MotionPriority::None);
+        assert_eq!(manager.current_priority(), MotionPriority::Normal);
+        // The reservation is gone, so the next motion competes with the playing one
+        assert!(!play(&mut manager, MotionPriority::Normal));
+    }
+
+    #[test]
+    fn force_always_wins() {
+        let mut manager = MotionManager::default();
+        assert!(play(&mut manager, MotionPriority::Normal));
+        assert!(!manager.reserve(MotionPriority::Idle));
+
+        assert!(manager.reserve(MotionPriority::Force));
+        assert!(play(&mut manager, MotionPriority::Force));
+        assert!(play(&mut manager, MotionPriority::Force));
+        assert_eq!(manager.current_priority(), MotionPriority::Force);
+    }
+
+    #[test]
+    fn priority_is_released_once_nothing_plays() {
+        let mut manager = MotionManager::default();
+        assert!(play(&mut manager, MotionPriority::Normal));
+
+        manager.player.advance(0.5);
+        manager.remove_finished();
+        assert_eq!(manager.current_priority(), MotionPriority::Normal);
+
+        manager.player.advance(0.5);
+        manager.remove_finished();
+        assert_eq!(manager.current_priority(), MotionPriority::None);
+        assert!(play(&mut manager, MotionPriority::Idle));
+    }
+}
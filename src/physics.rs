@@ -0,0 +1,478 @@
+use cubism::{
+    core::Model,
+    json::physics::{InputType, OutputType, Physics3},
+};
+use gdnative::prelude::Vector2;
+use std::f32::consts::PI;
+
+use crate::ids::ModelIds;
+
+const AIR_RESISTANCE: f32 = 5.0;
+const MAXIMUM_WEIGHT: f32 = 100.0;
+const MOVEMENT_THRESHOLD: f32 = 0.001;
+const MAX_DELTA_TIME: f32 = 5.0;
+
+fn normalized(v: Vector2) -> Vector2 {
+    let length = v.length();
+    if length > 0.0 {
+        v / length
+    } else {
+        v
+    }
+}
+
+fn rotate(v: Vector2, radian: f32) -> Vector2 {
+    let (sin, cos) = radian.sin_cos();
+    Vector2::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos)
+}
+
+fn radian_to_direction(radian: f32) -> Vector2 {
+    Vector2::new(radian.sin(), radian.cos())
+}
+
+/// Signed angle going from `from` to `to`, in `-PI..=PI`.
+fn direction_to_radian(from: Vector2, to: Vector2) -> f32 {
+    let mut radian = to.y.atan2(to.x) - from.y.atan2(from.x);
+    while radian < -PI {
+        radian += 2.0 * PI;
+    }
+    while radian > PI {
+        radian -= 2.0 * PI;
+    }
+
+    radian
+}
+
+#[derive(Copy, Clone)]
+struct Range {
+    minimum: f32,
+    default: f32,
+    maximum: f32,
+}
+
+/// Maps a parameter value from its own range onto the normalization range of the rig.
+fn normalize_parameter_value(
+    value: f32,
+    parameter: Range,
+    normalization: Range,
+    reflect: bool,
+) -> f32 {
+    let max_value = parameter.maximum.max(parameter.minimum);
+    let min_value = parameter.maximum.min(parameter.minimum);
+    let value = value.clamp(min_value, max_value);
+
+    let min_norm_value = normalization.minimum.min(normalization.maximum);
+    let max_norm_value = normalization.minimum.max(normalization.maximum);
+    let middle_norm_value = normalization.default;
+    let middle_value = min_value + (max_value - min_value) / 2.0;
+
+    let param_value = value - middle_value;
+    let (norm_length, param_length) = if param_value > 0.0 {
+        (max_norm_value - middle_norm_value, max_value - middle_value)
+    } else if param_value < 0.0 {
+        (min_norm_value - middle_norm_value, min_value - middle_value)
+    } else {
+        (0.0, 0.0)
+    };
+
+    let result = if param_length != 0.0 {
+        param_value * (norm_length / param_length) + middle_norm_value
+    } else if param_value == 0.0 {
+        middle_norm_value
+    } else {
+        0.0
+    };
+
+    if reflect {
+        result
+    } else {
+        -result
+    }
+}
+
+struct Particle {
+    mobility: f32,
+    delay: f32,
+    acceleration: f32,
+    radius: f32,
+    position: Vector2,
+    last_position: Vector2,
+    last_gravity: Vector2,
+    velocity: Vector2,
+}
+
+struct PhysicsInput {
+    parameter: usize,
+    range: Range,
+    weight: f32,
+    ty: InputType,
+    reflect: bool,
+}
+
+struct PhysicsOutput {
+    parameter: usize,
+    range: Range,
+    vertex_index: usize,
+    scale: f32,
+    weight: f32,
+    ty: OutputType,
+    reflect: bool,
+    /// Output value of the previous and the latest simulation step, before weighting.
+    previous_value: f32,
+    current_value: f32,
+}
+
+/// A single pendulum chain from the physics3 file along with its inputs and outputs.
+struct SubRig {
+    inputs: Vec<PhysicsInput>,
+    outputs: Vec<PhysicsOutput>,
+    particles: Vec<Particle>,
+    normalization_position: Range,
+    normalization_angle: Range,
+}
+
+impl SubRig {
+    fn reset(&mut self) {
+        let mut position = Vector2::new(0.0, 0.0);
+        for (i, particle) in self.particles.iter_mut().enumerate() {
+            if i > 0 {
+                position += Vector2::new(0.0, particle.radius);
+            }
+
+            particle.position = position;
+            particle.last_position = position;
+            particle.last_gravity = Vector2::new(0.0, 1.0);
+            particle.velocity = Vector2::new(0.0, 0.0);
+        }
+
+        for output in self.outputs.iter_mut() {
+            output.previous_value = 0.0;
+            output.current_value = 0.0;
+        }
+    }
+
+    fn update_particles(
+        &mut self,
+        translation: Vector2,
+        angle: f32,
+        gravity: Vector2,
+        wind: Vector2,
+        delta: f32,
+    ) {
+        let threshold = MOVEMENT_THRESHOLD * self.normalization_position.maximum;
+
+        // Particles hang towards +y, so the default gravity of (0, -1) leaves the
+        // direction from the input angle untouched
+        let gravity_rotation =
+            direction_to_radian(Vector2::new(0.0, 1.0), Vector2::new(gravity.x, -gravity.y));
+        let current_gravity = rotate(
+            normalized(radian_to_direction(angle.to_radians())),
+            gravity_rotation,
+        ) * gravity.length();
+
+        self.particles[0].position = translation;
+        for i in 1..self.particles.len() {
+            let parent_position = self.particles[i - 1].position;
+            let particle = &mut self.particles[i];
+
+            let force = current_gravity * particle.acceleration + wind;
+            particle.last_position = particle.position;
+
+            let delay = particle.delay * delta * 30.0;
+
+            let radian =
+                direction_to_radian(particle.last_gravity, current_gravity) / AIR_RESISTANCE;
+            let direction = rotate(particle.position - parent_position, radian);
+
+            particle.position =
+                parent_position + direction + particle.velocity * delay + force * delay * delay;
+
+            let new_direction = normalized(particle.position - parent_position);
+            particle.position = parent_position + new_direction * particle.radius;
+
+            if particle.position.x.abs() < threshold {
+                particle.position.x = 0.0;
+            }
+
+            if delay != 0.0 {
+                particle.velocity =
+                    (particle.position - particle.last_position) / delay * particle.mobility;
+            }
+
+            particle.last_gravity = current_gravity;
+        }
+    }
+
+    /// Runs one simulation step, reading inputs from `parameter_values` and storing the
+    /// unweighted outputs.
+    fn step(&mut self, parameter_values: &[f32], gravity: Vector2, wind: Vector2, delta: f32) {
+        let mut translation = Vector2::new(0.0, 0.0);
+        let mut angle: f32 = 0.0;
+
+        for input in self.inputs.iter() {
+            let weight = input.weight / MAXIMUM_WEIGHT;
+            let value = parameter_values[input.parameter];
+
+            match input.ty {
+                InputType::X => {
+                    translation.x += normalize_parameter_value(
+                        value,
+                        input.range,
+                        self.normalization_position,
+                        input.reflect,
+                    ) * weight
+                }
+                InputType::Y => {
+                    translation.y += normalize_parameter_value(
+                        value,
+                        input.range,
+                        self.normalization_position,
+                        input.reflect,
+                    ) * weight
+                }
+                InputType::Angle => {
+                    angle += normalize_parameter_value(
+                        value,
+                        input.range,
+                        self.normalization_angle,
+                        input.reflect,
+                    ) * weight
+                }
+            }
+        }
+
+        let translation = rotate(translation, (-angle).to_radians());
+
+        self.update_particles(translation, angle, gravity, wind, delta);
+
+        for output in self.outputs.iter_mut() {
+            let index = output.vertex_index;
+            if index < 1 || index >= self.particles.len() {
+                continue;
+            }
+
+            let direction = self.particles[index].position - self.particles[index - 1].position;
+            let value = match output.ty {
+                OutputType::X => direction.x,
+                OutputType::Y => direction.y,
+                OutputType::Angle => {
+                    let parent_gravity = if index >= 2 {
+                        self.particles[index - 1].position - self.particles[index - 2].position
+                    } else {
+                        -gravity
+                    };
+
+                    direction_to_radian(parent_gravity, direction)
+                }
+            };
+            let value = if output.reflect { -value } else { value };
+
+            output.previous_value = output.current_value;
+            output.current_value =
+                (value * output.scale).clamp(output.range.minimum, output.range.maximum);
+        }
+    }
+
+    /// Writes the outputs to the model, interpolated by `alpha` between the previous and
+    /// the latest step.
+    fn apply(&self, parameter_values: &mut [f32], alpha: f32) {
+        for output in self.outputs.iter() {
+            let value = output.previous_value * (1.0 - alpha) + output.current_value * alpha;
+            let weight = output.weight / MAXIMUM_WEIGHT;
+
+            let parameter = &mut parameter_values[output.parameter];
+            if weight >= 1.0 {
+                *parameter = value;
+            } else {
+                *parameter = *parameter * (1.0 - weight) + value * weight;
+            }
+        }
+    }
+}
+
+/// Runs the physics3 rig, moving output parameters based on input parameters.
+pub struct Physics {
+    rigs: Vec<SubRig>,
+    gravity: Vector2,
+    wind: Vector2,
+    /// Simulation steps per second, or 0 to step once per update.
+    fps: f32,
+    /// Time that has not been simulated yet when running at a fixed rate.
+    remaining_time: f32,
+    /// Parameter values seen by the last step, used to interpolate inputs across sub-steps.
+    input_cache: Vec<f32>,
+}
+
+impl Physics {
+    pub fn new(physics3: &Physics3, model: &Model, ids: &ModelIds) -> Self {
+        let moc = model.moc();
+        let parameter_range = |index: usize| Range {
+            minimum: moc.parameter_min()[index],
+            default: moc.parameter_default()[index],
+            maximum: moc.parameter_max()[index],
+        };
+
+        let rigs = physics3
+            .physics_settings
+            .iter()
+            .map(|setting| {
+                let normalization = &setting.normalization;
+
+                let mut rig = SubRig {
+                    inputs: setting
+                        .input
+                        .iter()
+                        .filter_map(|input| {
+                            let parameter = ids.parameter(&input.source.id)?;
+                            Some(PhysicsInput {
+                                parameter,
+                                range: parameter_range(parameter),
+                                weight: input.weight,
+                                ty: input.ty,
+                                reflect: input.reflect,
+                            })
+                        })
+                        .collect(),
+                    outputs: setting
+                        .output
+                        .iter()
+                        .filter_map(|output| {
+                            let parameter = ids.parameter(&output.destination.id)?;
+                            Some(PhysicsOutput {
+                                parameter,
+                                range: parameter_range(parameter),
+                                vertex_index: output.vertex_index,
+                                scale: output.scale,
+                                weight: output.weight,
+                                ty: output.ty,
+                                reflect: output.reflect,
+                                previous_value: 0.0,
+                                current_value: 0.0,
+                            })
+                        })
+                        .collect(),
+                    particles: setting
+                        .vertices
+                        .iter()
+                        .map(|vertex| Particle {
+                            mobility: vertex.mobility,
+                            delay: vertex.delay,
+                            acceleration: vertex.acceleration,
+                            radius: vertex.radius,
+                            position: Vector2::new(0.0, 0.0),
+                            last_position: Vector2::new(0.0, 0.0),
+                            last_gravity: Vector2::new(0.0, 1.0),
+                            velocity: Vector2::new(0.0, 0.0),
+                        })
+                        .collect(),
+                    normalization_position: Range {
+                        minimum: normalization.position.minimum,
+                        default: normalization.position.default,
+                        maximum: normalization.position.maximum,
+                    },
+                    normalization_angle: Range {
+                        minimum: normalization.angle.minimum,
+                        default: normalization.angle.default,
+                        maximum: normalization.angle.maximum,
+                    },
+                };
+                rig.reset();
+
+                rig
+            })
+            .filter(|rig| !rig.particles.is_empty())
+            .collect();
+
+        let forces = &physics3.meta.effective_forces;
+
+        Self {
+            rigs,
+            gravity: Vector2::new(forces.gravity.x, forces.gravity.y),
+            wind: Vector2::new(forces.wind.x, forces.wind.y),
+            fps: 0.0,
+            remaining_time: 0.0,
+            input_cache: model.parameter_values().to_vec(),
+        }
+    }
+
+    pub fn gravity(&self) -> Vector2 {
+        self.gravity
+    }
+
+    pub fn set_gravity(&mut self, gravity: Vector2) {
+        self.gravity = gravity;
+    }
+
+    pub fn wind(&self) -> Vector2 {
+        self.wind
+    }
+
+    pub fn set_wind(&mut self, wind: Vector2) {
+        self.wind = wind;
+    }
+
+    pub fn fps(&self) -> f32 {
+        self.fps
+    }
+
+    /// Sets a fixed simulation rate. A value of 0 or less steps once per update instead.
+    pub fn set_fps(&mut self, fps: f32) {
+        self.fps = fps.max(0.0);
+        self.remaining_time = 0.0;
+    }
+
+    /// Puts every pendulum back into its rest position, e.g. after teleporting the model.
+    pub fn reset(&mut self) {
+        for rig in self.rigs.iter_mut() {
+            rig.reset();
+        }
+        self.remaining_time = 0.0;
+    }
+
+    /// Advances the simulation by `delta` seconds and writes the outputs to the model.
+    ///
+    /// At a fixed rate, the simulation runs as many steps as fit into the elapsed time and
+    /// the outputs are interpolated for the time left over.
+    pub fn evaluate(&mut self, model: &mut Model, delta: f32) {
+        if delta <= 0.0 {
+            return;
+        }
+
+        let parameter_values = model.parameter_values_mut();
+
+        if self.fps <= 0.0 {
+            let delta = delta.min(MAX_DELTA_TIME);
+            self.input_cache.copy_from_slice(parameter_values);
+            for rig in self.rigs.iter_mut() {
+                rig.step(parameter_values, self.gravity, self.wind, delta);
+                rig.apply(parameter_values, 1.0);
+            }
+            return;
+        }
+
+        self.remaining_time += delta;
+        if self.remaining_time > MAX_DELTA_TIME {
+            self.remaining_time = 0.0;
+        }
+
+        let step_time = 1.0 / self.fps;
+        while self.remaining_time >= step_time {
+            // Inputs move smoothly from the last step towards the current values
+            let input_weight = step_time / self.remaining_time;
+            for (cached, value) in self.input_cache.iter_mut().zip(parameter_values.iter()) {
+                *cached = *cached * (1.0 - input_weight) + *value * input_weight;
+            }
+
+            for rig in self.rigs.iter_mut() {
+                rig.step(&self.input_cache, self.gravity, self.wind, step_time);
+            }
+
+            self.remaining_time -= step_time;
+        }
+
+        let alpha = self.remaining_time / step_time;
+        for rig in self.rigs.iter() {
+            rig.apply(parameter_values, alpha);
+        }
+    }
+}
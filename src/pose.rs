@@ -0,0 +1,144 @@
+use cubism::{core::Model, json::pose::Pose3};
+
+use crate::ids::ModelIds;
+
+const EPSILON: f32 = 0.001;
+const DEFAULT_FADE_TIME: f32 = 0.5;
+/// Shape of the fade curve for parts that are being hidden.
+const PHI: f32 = 0.5;
+/// Highest opacity a part being hidden may show through the part fading in.
+const BACK_OPACITY_THRESHOLD: f32 = 0.15;
+
+struct PosePart {
+    part: usize,
+    /// Parameter sharing the id of the part, selects the visible part in a group.
+    parameter: Option<usize>,
+    links: Vec<usize>,
+}
+
+/// Switches between parts in the pose3 groups so that only one part per group is visible.
+pub struct Pose {
+    groups: Vec<Vec<PosePart>>,
+    fade_time: f32,
+}
+
+impl Pose {
+    pub fn new(pose3: &Pose3, ids: &ModelIds) -> Self {
+        Self {
+            groups: pose3
+                .groups
+                .iter()
+                .map(|group| {
+                    group
+                        .iter()
+                        .filter_map(|item| {
+                            Some(PosePart {
+                                part: ids.part(&item.id)?,
+                                parameter: ids.parameter(&item.id),
+                                links: item.link.iter().filter_map(|id| ids.part(id)).collect(),
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                })
+                .filter(|group| !group.is_empty())
+                .collect(),
+            fade_time: if pose3.fade_in_time < 0.0 {
+                DEFAULT_FADE_TIME
+            } else {
+                pose3.fade_in_time
+            },
+        }
+    }
+
+    /// Shows the first part of every group and hides the rest.
+    pub fn reset(&self, model: &mut Model) {
+        for group in self.groups.iter() {
+            for (i, pose_part) in group.iter().enumerate() {
+                let value = if i == 0 { 1.0 } else { 0.0 };
+
+                model.part_opacities_mut()[pose_part.part] = value;
+                if let Some(parameter) = pose_part.parameter {
+                    model.parameter_values_mut()[parameter] = value;
+                }
+            }
+        }
+
+        self.copy_part_opacities(model);
+    }
+
+    /// Fades the selected part of every group in and its competitors out.
+    pub fn update(&self, model: &mut Model, delta: f32) {
+        let delta = delta.max(0.0);
+
+        for group in self.groups.iter() {
+            self.fade_group(model, group, delta);
+        }
+
+        self.copy_part_opacities(model);
+    }
+
+    fn fade_group(&self, model: &mut Model, group: &[PosePart], delta: f32) {
+        let mut visible = None;
+        let mut new_opacity = 1.0;
+
+        for (i, pose_part) in group.iter().enumerate() {
+            let value = pose_part
+                .parameter
+                .map_or(0.0, |parameter| model.parameter_values()[parameter]);
+            if value > EPSILON {
+                if visible.is_some() {
+                    break;
+                }
+
+                visible = Some(i);
+                new_opacity = if self.fade_time > 0.0 {
+                    (model.part_opacities()[pose_part.part] + delta / self.fade_time).min(1.0)
+                } else {
+                    1.0
+                };
+            }
+        }
+        let visible = match visible {
+            Some(visible) => visible,
+            None => {
+                new_opacity = 1.0;
+                0
+            }
+        };
+
+        let opacities = model.part_opacities_mut();
+        for (i, pose_part) in group.iter().enumerate() {
+            if i == visible {
+                opacities[pose_part.part] = new_opacity;
+                continue;
+            }
+
+            let mut max_opacity = if new_opacity < PHI {
+                new_opacity * (PHI - 1.0) / PHI + 1.0
+            } else {
+                (1.0 - new_opacity) * PHI / (1.0 - PHI)
+            };
+
+            let back_opacity = (1.0 - max_opacity) * (1.0 - new_opacity);
+            if back_opacity > BACK_OPACITY_THRESHOLD {
+                max_opacity = 1.0 - BACK_OPACITY_THRESHOLD / (1.0 - new_opacity);
+            }
+
+            let opacity = &mut opacities[pose_part.part];
+            if *opacity > max_opacity {
+                *opacity = max_opacity;
+            }
+        }
+    }
+
+    /// Linked parts follow the opacity of their parent part.
+    fn copy_part_opacities(&self, model: &mut Model) {
+        let opacities = model.part_opacities_mut();
+        for pose_part in self.groups.iter().flatten() {
+            let opacity = opacities[pose_part.part];
+            for link in pose_part.links.iter() {
+                opacities[*link] = opacity;
+            }
+        }
+    }
+}
@@ -0,0 +1,524 @@
+//! Renders drawables into an RGBA buffer on the CPU, for rendering without a GPU or without
+//! Godot running at all.
+
+use cubism::core::{ConstantFlags, Drawable, DynamicFlags};
+
+pub use crate::render::BlendMode;
+
+/// An RGBA8 image with straight alpha.
+pub struct RasterTexture {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl RasterTexture {
+    /// Whether there is exactly one RGBA8 pixel per texel.
+    pub fn is_valid(&self) -> bool {
+        self.pixels.len() == self.width * self.height * 4
+    }
+
+    /// Bilinearly filtered, premultiplied color at a uv with v pointing up, as used by Cubism.
+    fn sample(&self, u: f32, v: f32) -> [f32; 4] {
+        if self.width == 0 || self.height == 0 {
+            return [0.0; 4];
+        }
+
+        let x = u.clamp(0.0, 1.0) * self.width as f32 - 0.5;
+        let y = (1.0 - v.clamp(0.0, 1.0)) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let texel = |x: f32, y: f32| {
+            let x = (x.max(0.0) as usize).min(self.width - 1);
+            let y = (y.max(0.0) as usize).min(self.height - 1);
+            let i = (y * self.width + x) * 4;
+            let alpha = self.pixels[i + 3] as f32 / 255.0;
+            [
+                self.pixels[i] as f32 / 255.0 * alpha,
+                self.pixels[i + 1] as f32 / 255.0 * alpha,
+                self.pixels[i + 2] as f32 / 255.0 * alpha,
+                alpha,
+            ]
+        };
+
+        let (a, b, c, d) = (
+            texel(x0, y0),
+            texel(x0 + 1.0, y0),
+            texel(x0, y0 + 1.0),
+            texel(x0 + 1.0, y0 + 1.0),
+        );
+        let mut color = [0.0; 4];
+        for i in 0..4 {
+            let top = a[i] + (b[i] - a[i]) * fx;
+            let bottom = c[i] + (d[i] - c[i]) * fx;
+            color[i] = top + (bottom - top) * fy;
+        }
+
+        color
+    }
+}
+
+/// Everything needed to draw one drawable, in Cubism's y up model space.
+pub struct RasterDrawable<'a> {
+    pub vertex_positions: &'a [[f32; 2]],
+    pub vertex_uvs: &'a [[f32; 2]],
+    pub indices: &'a [u16],
+    pub opacity: f32,
+    pub render_order: i32,
+    pub texture_index: usize,
+    /// Indices of the drawables clipping this one.
+    pub masks: &'a [i32],
+    pub blend_mode: BlendMode,
+    pub double_sided: bool,
+    pub inverted_mask: bool,
+    pub visible: bool,
+}
+
+impl<'a> RasterDrawable<'a> {
+    pub fn from_drawable(drawable: &Drawable<'a>) -> Self {
+        Self {
+            vertex_positions: drawable.vertex_positions,
+            vertex_uvs: drawable.vertex_uvs,
+            indices: drawable.indices,
+            opacity: drawable.opacity,
+            render_order: drawable.render_order,
+            texture_index: drawable.texture_index as usize,
+            masks: drawable.masks,
+            blend_mode: BlendMode::from_flags(drawable.constant_flags),
+            double_sided: drawable
+                .constant_flags
+                .contains(ConstantFlags::IS_DOUBLE_SIDED),
+            inverted_mask: drawable
+                .constant_flags
+                .contains(ConstantFlags::IS_INVERTED_MASK),
+            visible: drawable.dynamic_flags.contains(DynamicFlags::IS_VISIBLE),
+        }
+    }
+}
+
+/// Size and origin of the model's canvas in pixels, and its pixels per model unit.
+#[derive(Debug, Copy, Clone)]
+pub struct RasterCanvas {
+    pub size: [f32; 2],
+    pub origin: [f32; 2],
+    pub ppu: f32,
+}
+
+/// Maps model space to image pixels, fitting the canvas into the image.
+struct Transform {
+    scale: f32,
+    offset: [f32; 2],
+}
+
+impl Transform {
+    fn new(canvas: &RasterCanvas, width: usize, height: usize) -> Self {
+        let scale =
+            (width as f32 / canvas.size[0].max(1.0)).min(height as f32 / canvas.size[1].max(1.0));
+
+        Self {
+            scale,
+            offset: [
+                (width as f32 - canvas.size[0] * scale) / 2.0 + canvas.origin[0] * scale,
+                (height as f32 - canvas.size[1] * scale) / 2.0 + canvas.origin[1] * scale,
+            ],
+        }
+    }
+
+    fn apply(&self, position: [f32; 2], ppu: f32) -> [f32; 2] {
+        [
+            self.offset[0] + position[0] * ppu * self.scale,
+            self.offset[1] - position[1] * ppu * self.scale,
+        ]
+    }
+}
+
+/// Whether a triangle faces the viewer. Front faces are counter clockwise in Cubism's y up
+/// model space.
+pub fn is_front_facing(a: [f32; 2], b: [f32; 2], c: [f32; 2]) -> bool {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0]) > 0.0
+}
+
+/// Calls `f` with the pixel index and uv of every pixel whose center is covered by the
+/// triangles of a drawable, skipping back faces unless the drawable is double sided.
+fn rasterize_triangles(
+    drawable: &RasterDrawable,
+    transform: &Transform,
+    ppu: f32,
+    width: usize,
+    height: usize,
+    mut f: impl FnMut(usize, [f32; 2]),
+) {
+    for triangle in drawable.indices.chunks_exact(3) {
+        let [i0, i1, i2] = [
+            triangle[0] as usize,
+            triangle[1] as usize,
+            triangle[2] as usize,
+        ];
+        let model = [
+            drawable.vertex_positions[i0],
+            drawable.vertex_positions[i1],
+            drawable.vertex_positions[i2],
+        ];
+
+        if !drawable.double_sided && !is_front_facing(model[0], model[1], model[2]) {
+            continue;
+        }
+
+        let mut p = [
+            transform.apply(model[0], ppu),
+            transform.apply(model[1], ppu),
+            transform.apply(model[2], ppu),
+        ];
+        let mut uv = [
+            drawable.vertex_uvs[i0],
+            drawable.vertex_uvs[i1],
+            drawable.vertex_uvs[i2],
+        ];
+
+        let mut area =
+            (p[1][0] - p[0][0]) * (p[2][1] - p[0][1]) - (p[1][1] - p[0][1]) * (p[2][0] - p[0][0]);
+        if area.abs() < f32::EPSILON {
+            continue;
+        }
+        // Same winding for every triangle, so an edge shared by two triangles is walked in
+        // opposite directions by each of them
+        if area < 0.0 {
+            p.swap(1, 2);
+            uv.swap(1, 2);
+            area = -area;
+        }
+
+        // Pixels exactly on an edge are only covered by one of the triangles sharing it, or
+        // they would be blended twice
+        let owns = |a: [f32; 2], b: [f32; 2]| {
+            let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+            dy > 0.0 || (dy == 0.0 && dx < 0.0)
+        };
+        let owned = [owns(p[1], p[2]), owns(p[2], p[0]), owns(p[0], p[1])];
+
+        // Pixels covered by the bounding box of the triangle, clamped to the image
+        let bounds = |axis: usize, limit: usize| {
+            let coordinates = p.iter().map(|v| v[axis]);
+            let min = coordinates
+                .clone()
+                .fold(f32::MAX, f32::min)
+                .floor()
+                .max(0.0);
+            let max = coordinates
+                .fold(f32::MIN, f32::max)
+                .ceil()
+                .min(limit as f32);
+            (min as usize, max as usize)
+        };
+        let (min_x, max_x) = bounds(0, width);
+        let (min_y, max_y) = bounds(1, height);
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+                // Barycentric weights, positive inside the triangle
+                let edge = |a: [f32; 2], b: [f32; 2]| {
+                    (a[0] - px) * (b[1] - py) - (a[1] - py) * (b[0] - px)
+                };
+                let w = [edge(p[1], p[2]), edge(p[2], p[0]), edge(p[0], p[1])];
+                if (0..3).any(|i| w[i] < 0.0 || (w[i] == 0.0 && !owned[i])) {
+                    continue;
+                }
+                let [w0, w1, w2] = w.map(|w| w / area);
+
+                f(
+                    y * width + x,
+                    [
+                        uv[0][0] * w0 + uv[1][0] * w1 + uv[2][0] * w2,
+                        uv[0][1] * w0 + uv[1][1] * w1 + uv[2][1] * w2,
+                    ],
+                );
+            }
+        }
+    }
+}
+
+/// Renders the drawables into a `width` by `height` RGBA8 buffer with straight alpha,
+/// fitting and centering the canvas in the image. Missing or invalid textures are drawn
+/// transparent.
+pub fn rasterize(
+    drawables: &[RasterDrawable],
+    textures: &[RasterTexture],
+    canvas: &RasterCanvas,
+    width: usize,
+    height: usize,
+) -> Vec<u8> {
+    let transform = Transform::new(canvas, width, height);
+    let empty = RasterTexture {
+        width: 0,
+        height: 0,
+        pixels: vec![],
+    };
+    let texture = |drawable: &RasterDrawable| {
+        textures
+            .get(drawable.texture_index)
+            .filter(|texture| texture.is_valid())
+            .unwrap_or(&empty)
+    };
+
+    // Premultiplied colors
+    let mut buffer = vec![[0.0f32; 4]; width * height];
+    // Coverage of every set of masks used so far
+    let mut mask_buffers: Vec<(Vec<i32>, Vec<f32>)> = vec![];
+
+    let mut order: Vec<&RasterDrawable> = drawables
+        .iter()
+        .filter(|drawable| drawable.visible && drawable.opacity > 0.0)
+        .collect();
+    order.sort_by_key(|drawable| drawable.render_order);
+
+    for drawable in order {
+        let mask = if drawable.masks.is_empty() {
+            None
+        } else {
+            let position = match mask_buffers
+                .iter()
+                .position(|(masks, _)| masks == drawable.masks)
+            {
+                Some(position) => position,
+                None => {
+                    let mut coverage = vec![0.0f32; width * height];
+                    for mask in drawable
+                        .masks
+                        .iter()
+                        .filter_map(|m| drawables.get(*m as usize))
+                    {
+                        let mask_texture = texture(mask);
+                        rasterize_triangles(
+                            mask,
+                            &transform,
+                            canvas.ppu,
+                            width,
+                            height,
+                            |i, uv| {
+                                let alpha = mask_texture.sample(uv[0], uv[1])[3];
+                                coverage[i] += alpha * (1.0 - coverage[i]);
+                            },
+                        );
+                    }
+
+                    mask_buffers.push((drawable.masks.to_vec(), coverage));
+                    mask_buffers.len() - 1
+                }
+            };
+            Some(&mask_buffers[position].1)
+        };
+
+        let drawable_texture = texture(drawable);
+        rasterize_triangles(drawable, &transform, canvas.ppu, width, height, |i, uv| {
+            let mut weight = drawable.opacity;
+            if let Some(mask) = mask {
+                weight *= if drawable.inverted_mask {
+                    1.0 - mask[i]
+                } else {
+                    mask[i]
+                };
+            }
+
+            let source = drawable_texture.sample(uv[0], uv[1]).map(|c| c * weight);
+            let destination = &mut buffer[i];
+            match drawable.blend_mode {
+                BlendMode::Normal => {
+                    for c in 0..4 {
+                        destination[c] = source[c] + destination[c] * (1.0 - source[3]);
+                    }
+                }
+                BlendMode::Additive => {
+                    for c in 0..3 {
+                        destination[c] = (destination[c] + source[c]).min(1.0);
+                    }
+                }
+                BlendMode::Multiplicative => {
+                    for c in 0..3 {
+                        destination[c] =
+                            source[c] * destination[c] + destination[c] * (1.0 - source[3]);
+                    }
+                }
+            }
+        });
+    }
+
+    buffer
+        .iter()
+        .flat_map(|color| {
+            let alpha = color[3].clamp(0.0, 1.0);
+            let straight = |c: f32| {
+                if alpha > 0.0 {
+                    ((c / alpha).clamp(0.0, 1.0) * 255.0).round() as u8
+                } else {
+                    0
+                }
+            };
+
+            [
+                straight(color[0]),
+                straight(color[1]),
+                straight(color[2]),
+                (alpha * 255.0).round() as u8,
+            ]
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FULL: [[f32; 2]; 4] = [[-2.0, -2.0], [2.0, -2.0], [2.0, 2.0], [-2.0, 2.0]];
+    const LEFT: [[f32; 2]; 4] = [[-2.0, -2.0], [0.0, -2.0], [0.0, 2.0], [-2.0, 2.0]];
+    const UVS: [[f32; 2]; 4] = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+    const INDICES: [u16; 6] = [0, 1, 2, 0, 2, 3];
+    /// A 4 by 4 pixel canvas centered on the model's origin, drawn into a 4 by 4 image.
+    const CANVAS: RasterCanvas = RasterCanvas {
+        size: [4.0, 4.0],
+        origin: [2.0, 2.0],
+        ppu: 1.0,
+    };
+
+    fn solid(color: [u8; 4]) -> RasterTexture {
+        RasterTexture {
+            width: 1,
+            height: 1,
+            pixels: color.to_vec(),
+        }
+    }
+
+    fn quad(positions: &[[f32; 2]], texture_index: usize, render_order: i32) -> RasterDrawable<'_> {
+        RasterDrawable {
+            vertex_positions: positions,
+            vertex_uvs: &UVS,
+            indices: &INDICES,
+            opacity: 1.0,
+            render_order,
+            texture_index,
+            masks: &[],
+            blend_mode: BlendMode::Normal,
+            double_sided: false,
+            inverted_mask: false,
+            visible: true,
+        }
+    }
+
+    fn pixel(pixels: &[u8], x: usize, y: usize) -> [u8; 4] {
+        let i = (y * 4 + x) * 4;
+        [pixels[i], pixels[i + 1], pixels[i + 2], pixels[i + 3]]
+    }
+
+    fn render(drawables: &[RasterDrawable], textures: &[RasterTexture]) -> Vec<u8> {
+        rasterize(drawables, textures, &CANVAS, 4, 4)
+    }
+
+    #[test]
+    fn front_faces_are_counter_clockwise() {
+        assert!(is_front_facing(FULL[0], FULL[1], FULL[2]));
+        assert!(!is_front_facing(FULL[0], FULL[2], FULL[1]));
+    }
+
+    #[test]
+    fn back_faces_are_only_drawn_when_double_sided() {
+        let textures = [solid([255, 0, 0, 255])];
+        let back_indices = [0, 2, 1, 0, 3, 2];
+        let mut back = quad(&FULL, 0, 0);
+        back.indices = &back_indices;
+
+        assert_eq!(pixel(&render(&[back], &textures), 1, 1), [0, 0, 0, 0]);
+
+        let mut back = quad(&FULL, 0, 0);
+        back.indices = &back_indices;
+        back.double_sided = true;
+        assert_eq!(pixel(&render(&[back], &textures), 1, 1), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn normal_blends_over() {
+        let textures = [solid([255, 0, 0, 255]), solid([0, 0, 255, 255])];
+        let mut top = quad(&FULL, 1, 1);
+        top.opacity = 0.5;
+
+        let pixels = render(&[quad(&FULL, 0, 0), top], &textures);
+
+        for (x, y) in [(0, 0), (3, 0), (0, 3), (3, 3)] {
+            assert_eq!(pixel(&pixels, x, y), [128, 0, 128, 255]);
+        }
+    }
+
+    #[test]
+    fn render_order_decides_what_is_on_top() {
+        let textures = [solid([255, 0, 0, 255]), solid([0, 0, 255, 255])];
+
+        let pixels = render(&[quad(&FULL, 0, 1), quad(&FULL, 1, 0)], &textures);
+
+        assert_eq!(pixel(&pixels, 1, 1), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn additive_adds_colors() {
+        let textures = [solid([255, 0, 0, 255]), solid([0, 255, 0, 255])];
+        let mut top = quad(&FULL, 1, 1);
+        top.blend_mode = BlendMode::Additive;
+
+        let pixels = render(&[quad(&FULL, 0, 0), top], &textures);
+
+        assert_eq!(pixel(&pixels, 1, 1), [255, 255, 0, 255]);
+    }
+
+    #[test]
+    fn multiplicative_multiplies_colors() {
+        let textures = [solid([255, 255, 255, 255]), solid([128, 64, 255, 255])];
+        let mut top = quad(&FULL, 1, 1);
+        top.blend_mode = BlendMode::Multiplicative;
+
+        let pixels = render(&[quad(&FULL, 0, 0), top], &textures);
+
+        assert_eq!(pixel(&pixels, 1, 1), [128, 64, 255, 255]);
+    }
+
+    #[test]
+    fn masks_clip_to_the_mask() {
+        let textures = [solid([255, 255, 255, 255]), solid([255, 0, 0, 255])];
+        let mut mask = quad(&LEFT, 0, 0);
+        mask.visible = false;
+        let mut masked = quad(&FULL, 1, 1);
+        masked.masks = &[0];
+
+        let pixels = render(&[mask, masked], &textures);
+
+        assert_eq!(pixel(&pixels, 1, 1), [255, 0, 0, 255]);
+        assert_eq!(pixel(&pixels, 2, 1), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn inverted_masks_clip_outside_the_mask() {
+        let textures = [solid([255, 255, 255, 255]), solid([255, 0, 0, 255])];
+        let mut mask = quad(&LEFT, 0, 0);
+        mask.visible = false;
+        let mut masked = quad(&FULL, 1, 1);
+        masked.masks = &[0];
+        masked.inverted_mask = true;
+
+        let pixels = render(&[mask, masked], &textures);
+
+        assert_eq!(pixel(&pixels, 1, 1), [0, 0, 0, 0]);
+        assert_eq!(pixel(&pixels, 2, 1), [255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn invalid_textures_are_transparent() {
+        let textures = [RasterTexture {
+            width: 2,
+            height: 2,
+            pixels: vec![255; 4],
+        }];
+
+        let pixels = render(&[quad(&FULL, 0, 0)], &textures);
+
+        assert!(pixels.iter().all(|c| *c == 0));
+    }
+}
@@ -0,0 +1,137 @@
+use gdnative::{
+    api::{File as GodotFile, ResourceLoader, Texture},
+    prelude::*,
+};
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use crate::rasterizer::RasterTexture;
+use crate::texture::texture_pixels;
+
+/// Source of the raw bytes for every file referenced by a model.
+pub trait ModelReader {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Pixels of a texture the reader can load without decoding it, e.g. one imported by
+    /// Godot. `None` if the texture has to be read and decoded.
+    fn read_texture(&self, _path: &Path) -> Option<RasterTexture> {
+        None
+    }
+}
+
+/// Destination for files written back out for a model, e.g. new expressions.
+pub trait ModelWriter {
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()>;
+}
+
+/// Picks the reader that can resolve `path`.
+pub fn reader_for(path: &Path) -> &'static dyn ModelReader {
+    if GodotFiles::handles(path.to_str().unwrap_or_default()) {
+        &GodotFiles
+    } else {
+        &FsFiles
+    }
+}
+
+/// Picks the writer that can resolve `path`.
+pub fn writer_for(path: &Path) -> &'static dyn ModelWriter {
+    if GodotFiles::handles(path.to_str().unwrap_or_default()) {
+        &GodotFiles
+    } else {
+        &FsFiles
+    }
+}
+
+/// Reads and writes files directly on the OS filesystem.
+pub struct FsFiles;
+
+impl ModelReader for FsFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+}
+
+impl ModelWriter for FsFiles {
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(path, bytes)
+    }
+}
+
+/// Reads and writes files through Godot's `File` API so that `res://`, `user://` and files packed
+/// into an exported PCK are visible.
+pub struct GodotFiles;
+
+impl GodotFiles {
+    /// Whether the path needs to go through Godot to be resolved.
+    pub fn handles(path: &str) -> bool {
+        path.starts_with("res://") || path.starts_with("user://")
+    }
+
+    fn godot_path(path: &Path) -> io::Result<String> {
+        // Godot only understands forward slashes
+        Ok(path
+            .to_str()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Path is not valid UTF-8"))?
+            .replace('\\', "/"))
+    }
+}
+
+impl ModelReader for GodotFiles {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = Self::godot_path(path)?;
+
+        let file = GodotFile::new();
+        if !file.file_exists(&path) {
+            return Err(io::Error::new(ErrorKind::NotFound, "File does not exist"));
+        }
+        file.open(&path, GodotFile::READ).map_err(|e| {
+            io::Error::new(ErrorKind::Other, format!("Unable to open file: {:?}", e))
+        })?;
+
+        let buffer = file.get_buffer(file.get_len());
+        file.close();
+
+        let bytes = buffer.read().to_vec();
+        Ok(bytes)
+    }
+
+    /// Loads `res://` textures as imported resources, as exported projects only contain the
+    /// imported texture and not the source image.
+    fn read_texture(&self, path: &Path) -> Option<RasterTexture> {
+        let path = Self::godot_path(path).ok()?;
+        let resource_loader = ResourceLoader::godot_singleton();
+        if !path.starts_with("res://") || !resource_loader.exists(&path, "Texture") {
+            return None;
+        }
+
+        let texture = resource_loader
+            .load(&path, "Texture", false)
+            .and_then(|resource| resource.cast::<Texture>())?;
+        let texture = texture_pixels(&texture);
+
+        Some(texture).filter(|texture| texture.is_valid() && texture.width > 0)
+    }
+}
+
+impl ModelWriter for GodotFiles {
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        let path = Self::godot_path(path)?;
+
+        let file = GodotFile::new();
+        file.open(&path, GodotFile::WRITE).map_err(|e| {
+            io::Error::new(ErrorKind::Other, format!("Unable to open file: {:?}", e))
+        })?;
+
+        file.store_buffer(ByteArray::from_slice(bytes));
+        file.close();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,379 @@
+use cubism::core::{ConstantFlags, Drawable, Model};
+use gdnative::{
+    api::{Texture, VisualServer},
+    prelude::*,
+};
+use std::path::PathBuf;
+
+use crate::changes::DrawableChanges;
+use crate::loader::{CubismModel, CubismModelFactory};
+use crate::rasterizer::is_front_facing;
+use crate::reader::reader_for;
+use crate::texture::TextureOptions;
+
+/// Draws mask drawables as pure coverage, overlapping masks combine into their union.
+const MASK_SHADER: &str = "
+shader_type canvas_item;
+render_mode blend_mix, unshaded;
+
+void fragment() {
+    COLOR = vec4(1.0, 1.0, 1.0, texture(TEXTURE, UV).a);
+}
+";
+
+/// Clips against `mask_texture` at `mask_uv`, which the renderer's `vertex()` must compute.
+const MASKED_CLIP: &str = "
+uniform sampler2D mask_texture;
+uniform bool inverted;
+
+varying vec2 mask_uv;
+
+float clip(float alpha) {
+    float mask = texture(mask_texture, mask_uv).a;
+    return alpha * (inverted ? 1.0 - mask : mask);
+}
+";
+
+const UNMASKED_CLIP: &str = "
+float clip(float alpha) {
+    return alpha;
+}
+";
+
+/// GLSL `float clip(float alpha)` applying the drawable's clipping mask, if it has one.
+pub fn clip_shader_function(masked: bool) -> &'static str {
+    if masked {
+        MASKED_CLIP
+    } else {
+        UNMASKED_CLIP
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    Normal,
+    Additive,
+    Multiplicative,
+}
+
+impl BlendMode {
+    pub fn from_flags(flags: ConstantFlags) -> Self {
+        if flags.contains(ConstantFlags::BLEND_ADDITIVE) {
+            BlendMode::Additive
+        } else if flags.contains(ConstantFlags::BLEND_MULTIPLICATIVE) {
+            BlendMode::Multiplicative
+        } else {
+            BlendMode::Normal
+        }
+    }
+
+    /// GLSL `vec4 blend(vec4 color)` turning a straight alpha color into the output expected by
+    /// `render_mode`. `blend_mul` ignores alpha, so multiplied colors are faded towards white
+    /// instead, giving Cubism's `dst * (src.rgb * src.a + 1 - src.a)`.
+    pub fn shader_function(self) -> &'static str {
+        match self {
+            BlendMode::Multiplicative => {
+                "
+vec4 blend(vec4 color) {
+    return vec4(mix(vec3(1.0), color.rgb, color.a), 1.0);
+}
+"
+            }
+            BlendMode::Normal | BlendMode::Additive => {
+                "
+vec4 blend(vec4 color) {
+    return color;
+}
+"
+            }
+        }
+    }
+
+    /// The matching `render_mode` for both canvas item and spatial shaders.
+    pub fn render_mode(self) -> &'static str {
+        match self {
+            BlendMode::Normal => "blend_mix",
+            BlendMode::Additive => "blend_add",
+            BlendMode::Multiplicative => "blend_mul",
+        }
+    }
+}
+
+/// Loads the model of a renderer node, logging failures. Returns the Godot `Error` code on
+/// failure.
+pub fn load_model(
+    path: &str,
+    file_name: &str,
+    texture_options: &TextureOptions,
+) -> Result<Instance<CubismModel, Shared>, i64> {
+    let res_path = PathBuf::from(path);
+
+    match CubismModelFactory::load(reader_for(&res_path), res_path, file_name, texture_options) {
+        Ok(model) => Ok(model.emplace().into_shared()),
+        Err(e) => {
+            godot_error!("{}", e);
+            Err(e.code as i64)
+        }
+    }
+}
+
+/// Locks the model of a renderer node for a frame, advancing it if `playing`, and draws it.
+pub fn process_model(
+    model: &Instance<CubismModel, Shared>,
+    playing: bool,
+    delta: f64,
+    draw: impl FnOnce(&CubismModel),
+) {
+    let result = unsafe { model.assume_safe() }.map_mut(|model, model_owner| {
+        if playing {
+            model.advance_frame(&model_owner, delta as f32);
+        }
+        model.create_textures();
+        draw(model);
+    });
+    if result.is_err() {
+        godot_error!("Unable to lock the model for drawing");
+    }
+}
+
+pub fn texture_rids(textures: &[Ref<Texture>]) -> Vec<Rid> {
+    textures
+        .iter()
+        .map(|texture| unsafe { texture.assume_safe() }.get_rid())
+        .collect()
+}
+
+/// Texture used by a drawable, out of the textures of the model.
+pub fn texture_rid(texture_rids: &[Rid], drawable: &Drawable) -> Rid {
+    texture_rids
+        .get(drawable.texture_index as usize)
+        .copied()
+        .unwrap_or_else(Rid::new)
+}
+
+/// Indices of the triangles of a drawable, without the back facing ones unless the drawable
+/// is double sided.
+pub fn front_indices(drawable: &Drawable) -> Int32Array {
+    if drawable
+        .constant_flags
+        .contains(ConstantFlags::IS_DOUBLE_SIDED)
+    {
+        return drawable.indices.iter().map(|i| *i as i32).collect();
+    }
+
+    let positions = drawable.vertex_positions;
+    drawable
+        .indices
+        .chunks_exact(3)
+        .filter(|triangle| {
+            is_front_facing(
+                positions[triangle[0] as usize],
+                positions[triangle[1] as usize],
+                positions[triangle[2] as usize],
+            )
+        })
+        .flatten()
+        .map(|i| *i as i32)
+        .collect()
+}
+
+/// Adds the triangles of a drawable to a canvas item, converting from Cubism's y up model
+/// space to Godot's y down pixels.
+pub fn add_triangles(
+    canvas_item: Rid,
+    drawable: &Drawable,
+    scale: f32,
+    offset: Vector2,
+    texture: Rid,
+) {
+    VisualServer::godot_singleton().canvas_item_add_triangle_array(
+        canvas_item,
+        front_indices(drawable),
+        drawable
+            .vertex_positions
+            .iter()
+            .map(|v| Vector2::new(v[0] * scale, -v[1] * scale) + offset)
+            .collect(),
+        ColorArray::new(),
+        drawable
+            .vertex_uvs
+            .iter()
+            .map(|uv| Vector2::new(uv[0], 1.0 - uv[1]))
+            .collect(),
+        Int32Array::new(),
+        Float32Array::new(),
+        texture,
+        -1,
+        Rid::new(),
+        false,
+        false,
+    );
+}
+
+/// A set of drawables used together as a clipping mask, rendered into their own viewport.
+struct MaskContext {
+    masks: Vec<usize>,
+    viewport: Rid,
+    canvas: Rid,
+    canvas_items: Vec<Rid>,
+}
+
+impl MaskContext {
+    fn new(masks: Vec<usize>, size: Vector2, material: Rid) -> Self {
+        let visual_server = VisualServer::godot_singleton();
+
+        let viewport = visual_server.viewport_create();
+        visual_server.viewport_set_size(viewport, size.x.ceil() as i64, size.y.ceil() as i64);
+        visual_server.viewport_set_usage(viewport, VisualServer::VIEWPORT_USAGE_2D);
+        visual_server.viewport_set_disable_3d(viewport, true);
+        visual_server.viewport_set_transparent_background(viewport, true);
+        // Viewport textures are upside down when sampled otherwise
+        visual_server.viewport_set_vflip(viewport, true);
+        visual_server.viewport_set_update_mode(viewport, VisualServer::VIEWPORT_UPDATE_ALWAYS);
+        visual_server.viewport_set_active(viewport, true);
+
+        let canvas = visual_server.canvas_create();
+        visual_server.viewport_attach_canvas(viewport, canvas);
+
+        let canvas_items = masks
+            .iter()
+            .map(|_| {
+                let canvas_item = visual_server.canvas_item_create();
+                visual_server.canvas_item_set_parent(canvas_item, canvas);
+                visual_server.canvas_item_set_material(canvas_item, material);
+                canvas_item
+            })
+            .collect();
+
+        Self {
+            masks,
+            viewport,
+            canvas,
+            canvas_items,
+        }
+    }
+
+    fn free(self) {
+        let visual_server = VisualServer::godot_singleton();
+        for canvas_item in self.canvas_items {
+            visual_server.free_rid(canvas_item);
+        }
+        visual_server.free_rid(self.canvas);
+        visual_server.free_rid(self.viewport);
+    }
+}
+
+/// Clipping mask textures covering the model's canvas, shared by drawables clipped by the
+/// same set of masks.
+pub struct Masks {
+    contexts: Vec<MaskContext>,
+    /// Index into `contexts` for every drawable.
+    drawable_contexts: Vec<Option<usize>>,
+    shader: Rid,
+    material: Rid,
+    /// Model pixels to mask texture pixels.
+    scale: f32,
+    pub canvas_size: Vector2,
+    pub canvas_origin: Vector2,
+}
+
+impl Masks {
+    /// Creates the mask textures of a model, `None` if nothing in it is masked.
+    ///
+    /// `resolution` is the size in pixels of the longest side of the textures.
+    pub fn new(model: &Model, drawables: &[Drawable], resolution: f32) -> Option<Self> {
+        if !model.moc().is_masked() {
+            return None;
+        }
+
+        let visual_server = VisualServer::godot_singleton();
+        let shader = visual_server.shader_create();
+        visual_server.shader_set_code(shader, MASK_SHADER);
+        let material = visual_server.material_create();
+        visual_server.material_set_shader(material, shader);
+
+        let (size, origin, _) = model.canvas_info();
+        let canvas_size = Vector2::new(size[0], size[1]);
+        let scale = resolution / canvas_size.x.max(canvas_size.y).max(1.0);
+
+        let mut contexts: Vec<MaskContext> = vec![];
+        let drawable_contexts = drawables
+            .iter()
+            .map(|drawable| {
+                if drawable.masks.is_empty() {
+                    return None;
+                }
+
+                let mut masks: Vec<usize> = drawable.masks.iter().map(|m| *m as usize).collect();
+                masks.sort_unstable();
+                masks.dedup();
+
+                Some(match contexts.iter().position(|c| c.masks == masks) {
+                    Some(context) => context,
+                    None => {
+                        contexts.push(MaskContext::new(masks, canvas_size * scale, material));
+                        contexts.len() - 1
+                    }
+                })
+            })
+            .collect();
+
+        Some(Self {
+            contexts,
+            drawable_contexts,
+            shader,
+            material,
+            scale,
+            canvas_size,
+            canvas_origin: Vector2::new(origin[0], origin[1]),
+        })
+    }
+
+    /// Mask texture clipping the drawable at `index`, if it is masked.
+    pub fn texture(&self, index: usize) -> Option<Rid> {
+        let context = self.drawable_contexts.get(index).copied().flatten()?;
+        Some(VisualServer::godot_singleton().viewport_get_texture(self.contexts[context].viewport))
+    }
+
+    /// Redraws the masks whose vertices changed, or every mask if `full` is set.
+    pub fn draw(
+        &self,
+        drawables: &[Drawable],
+        changes: &[DrawableChanges],
+        ppu: f32,
+        texture_rids: &[Rid],
+        full: bool,
+    ) {
+        let visual_server = VisualServer::godot_singleton();
+        // Masks are drawn in canvas space, scaled down to the mask texture
+        let offset = self.canvas_origin * self.scale;
+
+        for context in self.contexts.iter() {
+            for (mask, canvas_item) in context.masks.iter().zip(context.canvas_items.iter()) {
+                if !full && !changes[*mask].vertex_positions {
+                    continue;
+                }
+
+                let drawable = &drawables[*mask];
+                visual_server.canvas_item_clear(*canvas_item);
+                add_triangles(
+                    *canvas_item,
+                    drawable,
+                    ppu * self.scale,
+                    offset,
+                    texture_rid(texture_rids, drawable),
+                );
+            }
+        }
+    }
+
+    pub fn free(self) {
+        for context in self.contexts {
+            context.free();
+        }
+
+        let visual_server = VisualServer::godot_singleton();
+        visual_server.free_rid(self.material);
+        visual_server.free_rid(self.shader);
+    }
+}
@@ -0,0 +1,305 @@
+use cubism::core::{ConstantFlags, Drawable, DynamicFlags};
+use gdnative::{api::VisualServer, prelude::*};
+use std::collections::HashMap;
+
+use crate::changes::DrawableTracker;
+use crate::loader::CubismModel;
+use crate::render::{
+    add_triangles, clip_shader_function, load_model, process_model, texture_rid, texture_rids,
+    BlendMode, Masks,
+};
+use crate::texture::TextureOptions;
+
+/// Maps the drawable onto the mask texture covering the model's canvas.
+const MASK_UV_SHADER: &str = "
+uniform vec2 canvas_origin;
+uniform vec2 canvas_size;
+
+void vertex() {
+    mask_uv = (VERTEX + canvas_origin) / canvas_size;
+}
+";
+
+const DRAWABLE_SHADER: &str = "
+void fragment() {
+    vec4 color = texture(TEXTURE, UV) * COLOR;
+    color.a = clip(color.a);
+    COLOR = blend(color);
+}
+";
+
+fn drawable_shader_code(blend_mode: BlendMode, masked: bool) -> String {
+    format!(
+        "shader_type canvas_item;\nrender_mode {}, unshaded;\n{}{}{}{}",
+        blend_mode.render_mode(),
+        clip_shader_function(masked),
+        if masked { MASK_UV_SHADER } else { "" },
+        blend_mode.shader_function(),
+        DRAWABLE_SHADER
+    )
+}
+
+/// Draws a `CubismModel` with one canvas item per drawable.
+///
+/// The model's origin is placed at the node's position, with one pixel per model pixel.
+#[derive(NativeClass)]
+#[inherit(Node2D)]
+#[user_data(user_data::MutexData<CubismRenderer2D>)]
+pub struct CubismRenderer2D {
+    /// Directory containing the model, loaded on ready if `file_name` is set.
+    #[property]
+    path: String,
+    #[property]
+    file_name: String,
+    /// Whether the model is updated every frame.
+    #[property(default = true)]
+    playing: bool,
+    /// Size in pixels of the longest side of the clipping mask textures.
+    #[property(default = 1024)]
+    mask_resolution: i64,
+    /// Whether the textures of models loaded by the node are filtered.
+    #[property(default = true)]
+    texture_filter: bool,
+    /// Whether the textures of models loaded by the node have mipmaps.
+    #[property(default = true)]
+    texture_mipmaps: bool,
+    /// Whether the textures of models loaded by the node are converted to premultiplied alpha,
+    /// for custom materials. The node's own shaders expect straight alpha.
+    #[property]
+    premultiply_alpha: bool,
+
+    model: Option<Instance<CubismModel, Shared>>,
+    /// Texture of the model at the last draw, a replaced texture means redrawing everything.
+    texture_rids: Vec<Rid>,
+    /// State of the drawables at the last draw, to upload only what changed since.
+    tracker: DrawableTracker,
+    canvas_items: Vec<Rid>,
+    masks: Option<Masks>,
+    /// Drawable shaders by blend mode and whether they are masked.
+    shaders: HashMap<(BlendMode, bool), Rid>,
+    /// Material of every drawable that is masked or not normally blended.
+    materials: Vec<Option<Rid>>,
+}
+
+#[methods]
+impl CubismRenderer2D {
+    fn new(_owner: &Node2D) -> Self {
+        Self {
+            path: String::new(),
+            file_name: String::new(),
+            playing: true,
+            mask_resolution: 1024,
+            texture_filter: true,
+            texture_mipmaps: true,
+            premultiply_alpha: false,
+            model: None,
+            texture_rids: vec![],
+            tracker: DrawableTracker::default(),
+            canvas_items: vec![],
+            masks: None,
+            shaders: HashMap::new(),
+            materials: vec![],
+        }
+    }
+
+    #[export]
+    fn _ready(&mut self, owner: &Node2D) {
+        if !self.file_name.is_empty() {
+            let (path, file_name) = (self.path.clone(), self.file_name.clone());
+            self.load_model(owner, path, file_name);
+        }
+    }
+
+    #[export]
+    fn _exit_tree(&mut self, _owner: &Node2D) {
+        self.free_canvas_items();
+    }
+
+    #[export]
+    fn _process(&mut self, owner: &Node2D, delta: f64) {
+        if let Some(model) = self.model.clone() {
+            process_model(&model, self.playing, delta, |model| self.draw(owner, model));
+        }
+    }
+
+    /// Loads a model and starts drawing it. Returns a Godot `Error` code.
+    #[export]
+    pub fn load_model(&mut self, owner: &Node2D, path: String, file_name: String) -> i64 {
+        let texture_options = TextureOptions {
+            filter: self.texture_filter,
+            mipmaps: self.texture_mipmaps,
+            premultiply_alpha: self.premultiply_alpha,
+        };
+
+        match load_model(&path, &file_name, &texture_options) {
+            Ok(model) => {
+                self.path = path;
+                self.file_name = file_name;
+                self.set_model(owner, model);
+                0
+            }
+            Err(code) => code,
+        }
+    }
+
+    /// Draws an already loaded model, which may be shared with other nodes.
+    #[export]
+    pub fn set_model(&mut self, _owner: &Node2D, model: Instance<CubismModel, Shared>) {
+        self.free_canvas_items();
+
+        self.model = Some(model);
+    }
+
+    #[export]
+    pub fn model(&self, _owner: &Node2D) -> Option<Instance<CubismModel, Shared>> {
+        self.model.clone()
+    }
+
+    fn free_canvas_items(&mut self) {
+        let visual_server = VisualServer::godot_singleton();
+        for canvas_item in self.canvas_items.drain(..) {
+            visual_server.free_rid(canvas_item);
+        }
+        for material in self.materials.drain(..).flatten() {
+            visual_server.free_rid(material);
+        }
+        if let Some(masks) = self.masks.take() {
+            masks.free();
+        }
+        self.tracker.clear();
+        for (_, shader) in self.shaders.drain() {
+            visual_server.free_rid(shader);
+        }
+    }
+
+    fn create_canvas_items(&mut self, owner: &Node2D, model: &CubismModel, drawables: &[Drawable]) {
+        let visual_server = VisualServer::godot_singleton();
+        let parent = owner.get_canvas_item();
+
+        self.canvas_items = drawables
+            .iter()
+            .map(|_| {
+                let canvas_item = visual_server.canvas_item_create();
+                visual_server.canvas_item_set_parent(canvas_item, parent);
+                canvas_item
+            })
+            .collect();
+        self.masks = Masks::new(
+            model.user_model().model(),
+            drawables,
+            self.mask_resolution as f32,
+        );
+
+        self.materials = vec![None; drawables.len()];
+        for drawable in drawables.iter() {
+            let blend_mode = BlendMode::from_flags(drawable.constant_flags);
+            let mask_texture = self
+                .masks
+                .as_ref()
+                .and_then(|masks| masks.texture(drawable.index));
+            if blend_mode == BlendMode::Normal && mask_texture.is_none() {
+                continue;
+            }
+
+            let material = visual_server.material_create();
+            visual_server
+                .material_set_shader(material, self.shader(blend_mode, mask_texture.is_some()));
+
+            if let (Some(masks), Some(mask_texture)) = (&self.masks, mask_texture) {
+                visual_server.material_set_param(
+                    material,
+                    "mask_texture",
+                    mask_texture.to_variant(),
+                );
+                visual_server.material_set_param(
+                    material,
+                    "inverted",
+                    drawable
+                        .constant_flags
+                        .contains(ConstantFlags::IS_INVERTED_MASK)
+                        .to_variant(),
+                );
+                visual_server.material_set_param(
+                    material,
+                    "canvas_origin",
+                    masks.canvas_origin.to_variant(),
+                );
+                visual_server.material_set_param(
+                    material,
+                    "canvas_size",
+                    masks.canvas_size.to_variant(),
+                );
+            }
+
+            visual_server.canvas_item_set_material(self.canvas_items[drawable.index], material);
+            self.materials[drawable.index] = Some(material);
+        }
+    }
+
+    fn shader(&mut self, blend_mode: BlendMode, masked: bool) -> Rid {
+        *self.shaders.entry((blend_mode, masked)).or_insert_with(|| {
+            let visual_server = VisualServer::godot_singleton();
+            let shader = visual_server.shader_create();
+            visual_server.shader_set_code(shader, drawable_shader_code(blend_mode, masked));
+            shader
+        })
+    }
+
+    /// Uploads the drawables that changed since the last draw, or everything if the canvas
+    /// items were just created.
+    fn draw(&mut self, owner: &Node2D, model: &CubismModel) {
+        let user_model = model.user_model();
+        let drawables: Vec<Drawable> = user_model.drawables().collect();
+
+        let mut full = self.canvas_items.len() != drawables.len();
+        if full {
+            self.free_canvas_items();
+            self.create_canvas_items(owner, model, &drawables);
+        }
+
+        let texture_rids = texture_rids(model.loaded_textures());
+        if texture_rids != self.texture_rids {
+            self.texture_rids = texture_rids;
+            full = true;
+        }
+
+        let changes = self.tracker.update(&drawables);
+        let visual_server = VisualServer::godot_singleton();
+        let (_, _, ppu) = user_model.model().canvas_info();
+
+        if let Some(masks) = &self.masks {
+            masks.draw(&drawables, &changes, ppu, &self.texture_rids, full);
+        }
+
+        for (drawable, changes) in drawables.iter().zip(changes.iter()) {
+            let canvas_item = self.canvas_items[drawable.index];
+
+            if full || changes.visibility {
+                visual_server.canvas_item_set_visible(
+                    canvas_item,
+                    drawable.dynamic_flags.contains(DynamicFlags::IS_VISIBLE),
+                );
+            }
+            if full || changes.render_order {
+                // Sibling canvas items are drawn in draw index order
+                visual_server.canvas_item_set_draw_index(canvas_item, drawable.render_order as i64);
+            }
+            if full || changes.opacity {
+                visual_server.canvas_item_set_self_modulate(
+                    canvas_item,
+                    Color::rgba(1.0, 1.0, 1.0, drawable.opacity),
+                );
+            }
+            if full || changes.vertex_positions {
+                visual_server.canvas_item_clear(canvas_item);
+                add_triangles(
+                    canvas_item,
+                    drawable,
+                    ppu,
+                    Vector2::zero(),
+                    texture_rid(&self.texture_rids, drawable),
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+use cubism::core::{ConstantFlags, Drawable, DynamicFlags};
+use gdnative::{
+    api::{ArrayMesh, Material, Mesh, MeshInstance, Shader, ShaderMaterial},
+    prelude::*,
+};
+use std::collections::HashMap;
+
+use crate::changes::DrawableTracker;
+use crate::loader::CubismModel;
+use crate::render::{
+    clip_shader_function, load_model, process_model, texture_rids, BlendMode, Masks,
+};
+use crate::texture::TextureOptions;
+
+/// Maps the drawable onto the mask texture covering the model's canvas.
+const MASK_UV_SHADER: &str = "
+uniform vec2 canvas_origin;
+uniform vec2 canvas_size;
+uniform float ppu;
+
+void vertex() {
+    mask_uv = (vec2(VERTEX.x, -VERTEX.y) * ppu + canvas_origin) / canvas_size;
+}
+";
+
+const DRAWABLE_SHADER: &str = "
+uniform sampler2D albedo_texture : hint_albedo;
+uniform float opacity = 1.0;
+
+void fragment() {
+    vec4 color = texture(albedo_texture, UV);
+    color.a = clip(color.a * opacity);
+    color = blend(color);
+    ALBEDO = color.rgb;
+    ALPHA = color.a;
+}
+";
+
+fn drawable_shader_code(blend_mode: BlendMode, double_sided: bool, masked: bool) -> String {
+    format!(
+        "shader_type spatial;\nrender_mode {}, {}, unshaded;\n{}{}{}{}",
+        blend_mode.render_mode(),
+        // Cubism's front faces are counter clockwise, Godot's are clockwise
+        if double_sided {
+            "cull_disabled"
+        } else {
+            "cull_front"
+        },
+        clip_shader_function(masked),
+        if masked { MASK_UV_SHADER } else { "" },
+        blend_mode.shader_function(),
+        DRAWABLE_SHADER
+    )
+}
+
+/// Draws a `CubismModel` with one `MeshInstance` per drawable, so only the drawables that
+/// changed are uploaded again.
+///
+/// One world unit covers `ppu` pixels of the model's canvas, with the model's origin at
+/// the node's origin. Loading, playback and texture properties work as in `CubismRenderer2D`.
+#[derive(NativeClass)]
+#[inherit(Spatial)]
+#[user_data(user_data::MutexData<CubismRenderer3D>)]
+pub struct CubismRenderer3D {
+    #[property]
+    path: String,
+    #[property]
+    file_name: String,
+    #[property(default = true)]
+    playing: bool,
+    #[property(default = 1024)]
+    mask_resolution: i64,
+    #[property(default = true)]
+    texture_filter: bool,
+    #[property(default = true)]
+    texture_mipmaps: bool,
+    #[property]
+    premultiply_alpha: bool,
+    /// Distance along z between drawables of consecutive render orders.
+    #[property(default = 0.001)]
+    depth_offset: f32,
+
+    model: Option<Instance<CubismModel, Shared>>,
+    /// Mesh instance of every drawable, children of the node.
+    mesh_instances: Vec<Ref<MeshInstance>>,
+    /// Mesh of every drawable.
+    meshes: Vec<Ref<ArrayMesh>>,
+    /// Texture of the model at the last draw, a replaced texture means redrawing everything.
+    texture_rids: Vec<Rid>,
+    /// State of the drawables at the last draw, to update only what changed since.
+    tracker: DrawableTracker,
+    masks: Option<Masks>,
+    /// Drawable shaders by blend mode, double sidedness and whether they are masked.
+    shaders: HashMap<(BlendMode, bool, bool), Ref<Shader>>,
+    /// Material of every drawable.
+    materials: Vec<Ref<ShaderMaterial>>,
+}
+
+#[methods]
+impl CubismRenderer3D {
+    fn new(_owner: &Spatial) -> Self {
+        Self {
+            path: String::new(),
+            file_name: String::new(),
+            playing: true,
+            mask_resolution: 1024,
+            texture_filter: true,
+            texture_mipmaps: true,
+            premultiply_alpha: false,
+            depth_offset: 0.001,
+            model: None,
+            mesh_instances: vec![],
+            meshes: vec![],
+            texture_rids: vec![],
+            tracker: DrawableTracker::default(),
+            masks: None,
+            shaders: HashMap::new(),
+            materials: vec![],
+        }
+    }
+
+    #[export]
+    fn _ready(&mut self, owner: &Spatial) {
+        if !self.file_name.is_empty() {
+            let (path, file_name) = (self.path.clone(), self.file_name.clone());
+            self.load_model(owner, path, file_name);
+        }
+    }
+
+    #[export]
+    fn _exit_tree(&mut self, _owner: &Spatial) {
+        self.free_materials();
+    }
+
+    #[export]
+    fn _process(&mut self, owner: &Spatial, delta: f64) {
+        if let Some(model) = self.model.clone() {
+            process_model(&model, self.playing, delta, |model| self.draw(owner, model));
+        }
+    }
+
+    /// Loads a model and starts drawing it. Returns a Godot `Error` code.
+    #[export]
+    pub fn load_model(&mut self, owner: &Spatial, path: String, file_name: String) -> i64 {
+        let texture_options = TextureOptions {
+            filter: self.texture_filter,
+            mipmaps: self.texture_mipmaps,
+            premultiply_alpha: self.premultiply_alpha,
+        };
+
+        match load_model(&path, &file_name, &texture_options) {
+            Ok(model) => {
+                self.path = path;
+                self.file_name = file_name;
+                self.set_model(owner, model);
+                0
+            }
+            Err(code) => code,
+        }
+    }
+
+    /// Draws an already loaded model, which may be shared with other nodes.
+    #[export]
+    pub fn set_model(&mut self, _owner: &Spatial, model: Instance<CubismModel, Shared>) {
+        self.free_materials();
+
+        self.model = Some(model);
+    }
+
+    #[export]
+    pub fn model(&self, _owner: &Spatial) -> Option<Instance<CubismModel, Shared>> {
+        self.model.clone()
+    }
+
+    /// Mesh instance of every drawable, in drawable index order.
+    #[export]
+    pub fn mesh_instances(&self, _owner: &Spatial) -> Vec<Ref<MeshInstance>> {
+        self.mesh_instances.clone()
+    }
+
+    fn free_materials(&mut self) {
+        for mesh_instance in self.mesh_instances.drain(..) {
+            unsafe { mesh_instance.assume_safe() }.queue_free();
+        }
+        self.meshes.clear();
+        self.materials.clear();
+        self.shaders.clear();
+        // New materials need the textures set again
+        self.texture_rids.clear();
+        self.tracker.clear();
+        if let Some(masks) = self.masks.take() {
+            masks.free();
+        }
+    }
+
+    fn create_materials(&mut self, owner: &Spatial, model: &CubismModel, drawables: &[Drawable]) {
+        let user_model = model.user_model();
+        let (_, _, ppu) = user_model.model().canvas_info();
+        self.masks = Masks::new(user_model.model(), drawables, self.mask_resolution as f32);
+
+        self.materials = Vec::with_capacity(drawables.len());
+        for drawable in drawables.iter() {
+            let mask_texture = self
+                .masks
+                .as_ref()
+                .and_then(|masks| masks.texture(drawable.index));
+
+            let material = ShaderMaterial::new();
+            material.set_shader(
+                self.shader(
+                    BlendMode::from_flags(drawable.constant_flags),
+                    drawable
+                        .constant_flags
+                        .contains(ConstantFlags::IS_DOUBLE_SIDED),
+                    mask_texture.is_some(),
+                ),
+            );
+
+            if let (Some(masks), Some(mask_texture)) = (&self.masks, mask_texture) {
+                material.set_shader_param("mask_texture", mask_texture);
+                material.set_shader_param(
+                    "inverted",
+                    drawable
+                        .constant_flags
+                        .contains(ConstantFlags::IS_INVERTED_MASK),
+                );
+                material.set_shader_param("canvas_origin", masks.canvas_origin);
+                material.set_shader_param("canvas_size", masks.canvas_size);
+                material.set_shader_param("ppu", ppu);
+            }
+
+            let material = material.into_shared();
+            let mesh = ArrayMesh::new().into_shared();
+            let mesh_instance = MeshInstance::new();
+            mesh_instance.set_mesh(mesh.clone());
+            mesh_instance.set_material_override(material.clone());
+            let mesh_instance = mesh_instance.into_shared();
+            owner.add_child(mesh_instance.clone(), false);
+
+            self.materials.push(material);
+            self.mesh_instances.push(mesh_instance);
+            self.meshes.push(mesh);
+        }
+    }
+
+    fn shader(&mut self, blend_mode: BlendMode, double_sided: bool, masked: bool) -> Ref<Shader> {
+        self.shaders
+            .entry((blend_mode, double_sided, masked))
+            .or_insert_with(|| {
+                let shader = Shader::new();
+                shader.set_code(drawable_shader_code(blend_mode, double_sided, masked));
+                shader.into_shared()
+            })
+            .clone()
+    }
+
+    /// Uploads the drawables that changed since the last draw, or everything if the mesh
+    /// instances were just created.
+    fn draw(&mut self, owner: &Spatial, model: &CubismModel) {
+        let user_model = model.user_model();
+        let drawables: Vec<Drawable> = user_model.drawables().collect();
+
+        let mut full = self.materials.len() != drawables.len();
+        if full {
+            self.free_materials();
+            self.create_materials(owner, model, &drawables);
+        }
+
+        let texture_rids = texture_rids(model.loaded_textures());
+        if texture_rids != self.texture_rids {
+            self.texture_rids = texture_rids;
+            for (drawable, material) in drawables.iter().zip(self.materials.iter()) {
+                if let Some(texture) = model.loaded_textures().get(drawable.texture_index as usize)
+                {
+                    unsafe { material.assume_safe() }
+                        .set_shader_param("albedo_texture", texture.clone());
+                }
+            }
+            full = true;
+        }
+
+        let changes = self.tracker.update(&drawables);
+        let (_, _, ppu) = user_model.model().canvas_info();
+        if let Some(masks) = &self.masks {
+            masks.draw(&drawables, &changes, ppu, &self.texture_rids, full);
+        }
+
+        for (drawable, changes) in drawables.iter().zip(changes.iter()) {
+            let mesh_instance = unsafe { self.mesh_instances[drawable.index].assume_safe() };
+            let material = unsafe { self.materials[drawable.index].assume_safe() };
+
+            if full || changes.visibility {
+                mesh_instance
+                    .set_visible(drawable.dynamic_flags.contains(DynamicFlags::IS_VISIBLE));
+            }
+            if full || changes.opacity {
+                material.set_shader_param("opacity", drawable.opacity);
+            }
+            if full || changes.render_order {
+                material
+                    .set_render_priority(render_priority(drawable.render_order, drawables.len()));
+            }
+            if full || changes.render_order || changes.vertex_positions {
+                let mesh = unsafe { self.meshes[drawable.index].assume_safe() };
+                mesh.clear_surfaces();
+                if !drawable.indices.is_empty() {
+                    mesh.add_surface_from_arrays(
+                        Mesh::PRIMITIVE_TRIANGLES,
+                        self.surface_arrays(drawable),
+                        VariantArray::new_shared(),
+                        Mesh::ARRAY_COMPRESS_DEFAULT,
+                    );
+                }
+            }
+        }
+    }
+
+    fn surface_arrays(&self, drawable: &Drawable) -> VariantArray {
+        let depth = drawable.render_order as f32 * self.depth_offset;
+
+        let arrays = VariantArray::new();
+        arrays.resize(Mesh::ARRAY_MAX as i32);
+        arrays.set(
+            Mesh::ARRAY_VERTEX as i32,
+            drawable
+                .vertex_positions
+                .iter()
+                .map(|v| Vector3::new(v[0], v[1], depth))
+                .collect::<Vector3Array>(),
+        );
+        arrays.set(
+            Mesh::ARRAY_TEX_UV as i32,
+            drawable
+                .vertex_uvs
+                .iter()
+                .map(|uv| Vector2::new(uv[0], 1.0 - uv[1]))
+                .collect::<Vector2Array>(),
+        );
+        arrays.set(
+            Mesh::ARRAY_INDEX as i32,
+            drawable
+                .indices
+                .iter()
+                .map(|i| *i as i32)
+                .collect::<Int32Array>(),
+        );
+
+        arrays.into_shared()
+    }
+}
+
+/// Spreads the render orders over Godot's material render priorities, which sort transparent
+/// materials before their depth does. Drawables sharing a priority are still sorted by the
+/// `depth_offset` between them.
+fn render_priority(render_order: i32, count: usize) -> i64 {
+    let range = Material::RENDER_PRIORITY_MAX - Material::RENDER_PRIORITY_MIN + 1;
+    let priority = render_order.max(0) as i64 * range / count.max(1) as i64;
+
+    (Material::RENDER_PRIORITY_MIN + priority).min(Material::RENDER_PRIORITY_MAX)
+}
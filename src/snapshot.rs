@@ -0,0 +1,39 @@
+/// Smallest difference between two values that counts as a change.
+const EPSILON: f32 = 0.0001;
+
+/// Saved parameter values and part opacities of a model.
+pub struct Snapshot {
+    parameters: Vec<f32>,
+    part_opacities: Vec<f32>,
+}
+
+impl Snapshot {
+    pub fn capture(parameters: &[f32], part_opacities: &[f32]) -> Self {
+        Self {
+            parameters: parameters.to_vec(),
+            part_opacities: part_opacities.to_vec(),
+        }
+    }
+
+    pub fn restore(&self, parameters: &mut [f32], part_opacities: &mut [f32]) {
+        parameters.copy_from_slice(&self.parameters);
+        part_opacities.copy_from_slice(&self.part_opacities);
+    }
+
+    pub fn parameters(&self) -> &[f32] {
+        &self.parameters
+    }
+
+    pub fn part_opacities(&self) -> &[f32] {
+        &self.part_opacities
+    }
+}
+
+/// Index and difference, `to - from`, of every value that changed.
+pub fn diff<'a>(from: &'a [f32], to: &'a [f32]) -> impl Iterator<Item = (usize, f32)> + 'a {
+    from.iter()
+        .zip(to.iter())
+        .enumerate()
+        .map(|(i, (from, to))| (i, to - from))
+        .filter(|(_, difference)| difference.abs() > EPSILON)
+}
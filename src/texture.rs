@@ -0,0 +1,139 @@
+use gdnative::{
+    api::{Image, ImageTexture, Texture},
+    prelude::*,
+};
+use png::{ColorType, Decoder, DecodingError, Transformations};
+
+use crate::rasterizer::RasterTexture;
+
+/// How model textures are created when loading.
+#[derive(Debug, Copy, Clone)]
+pub struct TextureOptions {
+    pub filter: bool,
+    pub mipmaps: bool,
+    /// Converts the textures to premultiplied alpha, for shaders that blend with
+    /// `blend_premul_alpha`. The decoded images stay straight.
+    pub premultiply_alpha: bool,
+}
+
+impl Default for TextureOptions {
+    fn default() -> Self {
+        Self {
+            filter: true,
+            mipmaps: true,
+            premultiply_alpha: false,
+        }
+    }
+}
+
+impl TextureOptions {
+    fn flags(&self) -> i64 {
+        let mut flags = 0;
+        if self.filter {
+            flags |= Texture::FLAG_FILTER;
+        }
+        if self.mipmaps {
+            flags |= Texture::FLAG_MIPMAPS;
+        }
+
+        flags
+    }
+}
+
+/// Decodes a PNG into RGBA8 pixels with straight alpha. Does not need Godot.
+pub fn decode_png(bytes: &[u8]) -> Result<RasterTexture, DecodingError> {
+    let mut decoder = Decoder::new(bytes);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer)?;
+    buffer.truncate(info.buffer_size());
+
+    let pixels = match info.color_type {
+        ColorType::Rgba => buffer,
+        ColorType::Rgb => buffer
+            .chunks_exact(3)
+            .flat_map(|c| [c[0], c[1], c[2], 255])
+            .collect(),
+        ColorType::GrayscaleAlpha => buffer
+            .chunks_exact(2)
+            .flat_map(|c| [c[0], c[0], c[0], c[1]])
+            .collect(),
+        // Palettes are expanded to Rgb or Rgba by the decoder
+        ColorType::Grayscale | ColorType::Indexed => {
+            buffer.iter().flat_map(|c| [*c, *c, *c, 255]).collect()
+        }
+    };
+
+    Ok(RasterTexture {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+/// Creates an `ImageTexture` from decoded pixels.
+pub fn create_texture(raster: &RasterTexture, options: &TextureOptions) -> Ref<Texture> {
+    let image = Image::new();
+    image.create_from_data(
+        raster.width as i64,
+        raster.height as i64,
+        false,
+        Image::FORMAT_RGBA8,
+        ByteArray::from_slice(&raster.pixels),
+    );
+    if options.premultiply_alpha {
+        image.premultiply_alpha();
+    }
+    if options.mipmaps {
+        if let Err(e) = image.generate_mipmaps(false) {
+            godot_warn!("Unable to generate texture mipmaps: {:?}", e);
+        }
+    }
+
+    let texture = ImageTexture::new();
+    texture.create_from_image(image, options.flags());
+
+    texture.upcast::<Texture>().into_shared()
+}
+
+/// Reads the pixels of a Godot texture back, e.g. for rendering it on the CPU. Textures
+/// that cannot be read are empty and drawn transparent.
+pub fn texture_pixels(texture: &Ref<Texture>) -> RasterTexture {
+    let empty = RasterTexture {
+        width: 0,
+        height: 0,
+        pixels: vec![],
+    };
+
+    let image = match unsafe { texture.assume_safe() }.get_data() {
+        Some(image) => image,
+        None => return empty,
+    };
+    let image = unsafe { image.assume_safe() };
+    // Imported textures are usually compressed, which cannot be converted directly
+    if image.is_compressed() {
+        if let Err(e) = image.decompress() {
+            godot_warn!("Unable to decompress texture: {:?}", e);
+            return empty;
+        }
+    }
+    image.convert(Image::FORMAT_RGBA8);
+
+    let raster = RasterTexture {
+        width: image.get_width() as usize,
+        height: image.get_height() as usize,
+        pixels: image.get_data().to_vec(),
+    };
+    if !raster.is_valid() {
+        godot_warn!(
+            "Unable to read texture: expected {} bytes of RGBA8, got {}",
+            raster.width * raster.height * 4,
+            raster.pixels.len()
+        );
+        return empty;
+    }
+
+    raster
+}
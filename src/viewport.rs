@@ -0,0 +1,221 @@
+use gdnative::{
+    api::{Shader, ShaderMaterial, Sprite, Texture, Viewport},
+    core_types::GodotError,
+    prelude::*,
+};
+
+use crate::loader::CubismModel;
+use crate::renderer_2d::CubismRenderer2D;
+
+/// Largest supported supersampling factor, the loop bound of `DOWNSAMPLE_SHADER`.
+const MAX_SUPERSAMPLING: i64 = 8;
+
+/// Averages every `factor` by `factor` block of texels into one pixel. Viewports blend
+/// their transparent background into premultiplied colors, so they are averaged as is.
+const DOWNSAMPLE_SHADER: &str = "
+shader_type canvas_item;
+render_mode blend_premul_alpha;
+
+uniform int factor = 1;
+
+void fragment() {
+    vec2 origin = UV - TEXTURE_PIXEL_SIZE * float(factor - 1) / 2.0;
+    vec4 sum = vec4(0.0);
+    for (int y = 0; y < 8; y++) {
+        if (y >= factor) {
+            break;
+        }
+        for (int x = 0; x < 8; x++) {
+            if (x >= factor) {
+                break;
+            }
+            sum += texture(TEXTURE, origin + vec2(float(x), float(y)) * TEXTURE_PIXEL_SIZE);
+        }
+    }
+    COLOR = sum / float(factor * factor);
+}
+";
+
+/// Renders a model offscreen into its own `Viewport` with a transparent background, fitted
+/// and centered in the resolution. The result is available through `get_texture()`.
+///
+/// The model is drawn into an internal viewport `supersampling` times larger than the
+/// resolution, which is then downsampled into this one so that the texture has clean edges.
+#[derive(NativeClass)]
+#[inherit(Viewport)]
+#[user_data(user_data::MutexData<CubismViewport>)]
+pub struct CubismViewport {
+    /// Directory containing the model, loaded on ready if `file_name` is set.
+    #[property]
+    path: String,
+    #[property]
+    file_name: String,
+
+    resolution: Vector2,
+    supersampling: i64,
+    renderer: Instance<CubismRenderer2D, Shared>,
+    /// Viewport the renderer draws into, at the supersampled size.
+    supersampled: Ref<Viewport>,
+    /// Draws the supersampled viewport into this one at the resolution.
+    sprite: Ref<Sprite>,
+    downsample: Ref<ShaderMaterial>,
+}
+
+#[methods]
+impl CubismViewport {
+    fn new(owner: &Viewport) -> Self {
+        let supersampled = Viewport::new();
+        Self::set_up_viewport(&supersampled);
+        let renderer = Instance::<CubismRenderer2D, Unique>::new().into_shared();
+        supersampled.add_child(renderer.base().clone(), false);
+
+        let shader = Shader::new();
+        shader.set_code(DOWNSAMPLE_SHADER);
+        let downsample = ShaderMaterial::new();
+        downsample.set_shader(shader);
+        let downsample = downsample.into_shared();
+
+        let sprite = Sprite::new();
+        sprite.set_centered(false);
+        sprite.set_material(downsample.clone());
+        if let Some(texture) = supersampled.get_texture() {
+            // The shader picks exact texels, filtering would blur them
+            unsafe { texture.assume_safe() }.set_flags(0);
+            sprite.set_texture(texture);
+        }
+        let sprite = sprite.into_shared();
+
+        let supersampled = supersampled.into_shared();
+        owner.add_child(supersampled.clone(), false);
+        owner.add_child(sprite.clone(), false);
+        Self::set_up_viewport(owner);
+
+        Self {
+            path: String::new(),
+            file_name: String::new(),
+            resolution: Vector2::new(512.0, 512.0),
+            supersampling: 2,
+            renderer,
+            supersampled,
+            sprite,
+            downsample,
+        }
+    }
+
+    fn set_up_viewport(viewport: &Viewport) {
+        viewport.set_usage(Viewport::USAGE_2D);
+        viewport.set_disable_3d(true);
+        viewport.set_transparent_background(true);
+        // Viewport textures are upside down when drawn otherwise
+        viewport.set_vflip(true);
+        viewport.set_update_mode(Viewport::UPDATE_ALWAYS);
+    }
+
+    #[export]
+    fn _ready(&mut self, owner: &Viewport) {
+        if let Some(texture) = owner.get_texture() {
+            unsafe { texture.assume_safe() }.set_flags(Texture::FLAG_FILTER);
+        }
+
+        if !self.file_name.is_empty() {
+            let (path, file_name) = (self.path.clone(), self.file_name.clone());
+            self.load_model(owner, path, file_name);
+        } else {
+            self.layout(owner);
+        }
+    }
+
+    /// Loads a model and starts rendering it. Returns a Godot `Error` code.
+    #[export]
+    pub fn load_model(&mut self, owner: &Viewport, path: String, file_name: String) -> i64 {
+        let code = unsafe { self.renderer.assume_safe() }
+            .map_mut(|renderer, renderer_owner| {
+                renderer.load_model(&renderer_owner, path, file_name)
+            })
+            .unwrap_or(GodotError::Locked as i64);
+        self.layout(owner);
+
+        code
+    }
+
+    /// Renders an already loaded model, which may be shared with other nodes.
+    #[export]
+    pub fn set_model(&mut self, owner: &Viewport, model: Instance<CubismModel, Shared>) {
+        let result = unsafe { self.renderer.assume_safe() }
+            .map_mut(|renderer, renderer_owner| renderer.set_model(&renderer_owner, model));
+        if result.is_err() {
+            godot_error!("Unable to lock the renderer");
+        }
+        self.layout(owner);
+    }
+
+    #[export]
+    pub fn model(&self, _owner: &Viewport) -> Option<Instance<CubismModel, Shared>> {
+        unsafe { self.renderer.assume_safe() }
+            .map(|renderer, renderer_owner| renderer.model(&renderer_owner))
+            .ok()
+            .flatten()
+    }
+
+    /// The renderer drawing into the viewport, for changing how the model is drawn.
+    #[export]
+    pub fn renderer(&self, _owner: &Viewport) -> Instance<CubismRenderer2D, Shared> {
+        self.renderer.clone()
+    }
+
+    /// Sets the size of the texture the model is fitted into, before supersampling.
+    #[export]
+    pub fn set_resolution(&mut self, owner: &Viewport, width: i64, height: i64) {
+        self.resolution = Vector2::new(width.max(1) as f32, height.max(1) as f32);
+        self.layout(owner);
+    }
+
+    #[export]
+    pub fn resolution(&self, _owner: &Viewport) -> Vector2 {
+        self.resolution
+    }
+
+    /// Sets how many times larger than the resolution the model is rendered, from 1 to 8.
+    #[export]
+    pub fn set_supersampling(&mut self, owner: &Viewport, factor: i64) {
+        self.supersampling = factor.max(1).min(MAX_SUPERSAMPLING);
+        self.layout(owner);
+    }
+
+    #[export]
+    pub fn supersampling(&self, _owner: &Viewport) -> i64 {
+        self.supersampling
+    }
+
+    /// Sizes the viewports and fits the model's canvas into them.
+    fn layout(&self, owner: &Viewport) {
+        let size = self.resolution * self.supersampling as f32;
+        owner.set_size(self.resolution);
+        unsafe { self.supersampled.assume_safe() }.set_size(size);
+
+        let sprite = unsafe { self.sprite.assume_safe() };
+        let sprite_scale = 1.0 / self.supersampling as f32;
+        sprite.set_scale(Vector2::new(sprite_scale, sprite_scale));
+        unsafe { self.downsample.assume_safe() }.set_shader_param("factor", self.supersampling);
+
+        let canvas_info = self.model(owner).and_then(|model| {
+            unsafe { model.assume_safe() }
+                .map(|model, _| model.user_model().model().canvas_info())
+                .ok()
+        });
+        let (canvas_size, canvas_origin) = match canvas_info {
+            Some((canvas_size, canvas_origin, _)) => (
+                Vector2::new(canvas_size[0], canvas_size[1]),
+                Vector2::new(canvas_origin[0], canvas_origin[1]),
+            ),
+            None => return,
+        };
+
+        let scale = (size.x / canvas_size.x.max(1.0)).min(size.y / canvas_size.y.max(1.0));
+        let offset = (size - canvas_size * scale) / 2.0;
+
+        let renderer = unsafe { self.renderer.base().assume_safe() };
+        renderer.set_scale(Vector2::new(scale, scale));
+        renderer.set_position(offset + canvas_origin * scale);
+    }
+}